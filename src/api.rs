@@ -1,45 +1,217 @@
+use crate::section;
+use anyhow::Context;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
 #[derive(Debug, Clone, Copy)]
 pub struct PageId(i64);
 #[derive(Debug, Clone, Copy)]
 pub struct RevId(i64);
 
+/// `assert=` sent with edits.
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Assert {
+    User,
+    Bot,
+}
+impl Assert {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Assert::User => "user",
+            Assert::Bot => "bot",
+        }
+    }
+}
+
+/// A BotPassword, as made on Special:BotPasswords.
+#[derive(serde::Deserialize)]
+pub struct Credentials {
+    /// `User@appid`
+    pub name: String,
+    pub password: String,
+}
+
+impl Credentials {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let (user, app) = self.name.rsplit_once('@').with_context(|| {
+            format!(
+                "bot name {:?} should look like User@appid, see Special:BotPasswords",
+                self.name
+            )
+        })?;
+        if user.is_empty() || app.is_empty() {
+            anyhow::bail!("bot name {:?} has an empty user or app id", self.name);
+        }
+        if !app
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            anyhow::bail!("bad app id {:?} in bot name {:?}", app, self.name);
+        }
+        if self.password.is_empty() {
+            anyhow::bail!("empty password for {}", self.name);
+        }
+        Ok(())
+    }
+}
+
+/// What editing needs besides the `Api`.
+pub struct Session {
+    pub token: String,
+    pub assert: Assert,
+    /// Edits are refused unless the profile allows them.
+    pub writable: bool,
+    /// Kept to log in again when the session runs out.
+    pub creds: Option<Credentials>,
+}
+
+impl Session {
+    /// Logs in and fetches a fresh edit token.
+    pub async fn login(&mut self, api: &mut mediawiki::api::Api) -> anyhow::Result<()> {
+        let creds = self
+            .creds
+            .as_ref()
+            .context("no credentials to log in with")?;
+        api.login(creds.name.as_str(), creds.password.as_str())
+            .await
+            .with_context(|| format!("logging in as {}", creds.name))?;
+        self.token = api.get_edit_token().await.context("getting edit token")?;
+        // what anonymous users get
+        if self.token == "+\\" {
+            anyhow::bail!(
+                "got an anonymous edit token after logging in as {}",
+                creds.name
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Edit errors that mean the session is gone rather than the edit being bad.
+const SESSION_LOST: &[&str] = &[
+    "badtoken",
+    "notloggedin",
+    "assertuserfailed",
+    "assertbotfailed",
+];
+
+/// Posts an edit, logging in again and retrying once if the session ran out.
+async fn post_edit(
+    api: &mut mediawiki::api::Api,
+    session: &mut Session,
+    mut params: std::collections::HashMap<String, String>,
+) -> anyhow::Result<serde_json::Value> {
+    if !session.writable {
+        anyhow::bail!("refusing to edit: the profile is not writable");
+    }
+    params.insert("assert".to_string(), session.assert.as_str().to_string());
+    let mut retried = false;
+    loop {
+        params.insert("token".to_string(), session.token.clone());
+        let res = api.post_query_api_json(&params).await?;
+        let code = res["error"]["code"].as_str().unwrap_or("");
+        if SESSION_LOST.contains(&code) && !retried && session.creds.is_some() {
+            println!("session lost ({}), logging in again", code);
+            session.login(api).await?;
+            retried = true;
+            continue;
+        }
+        return Ok(res);
+    }
+}
+
 pub struct PageMeta {
     pub title: String,
     pub revid: RevId,
     pub pageid: PageId,
+    /// Who made the current revision, and when.
+    pub last_editor: String,
+    pub last_edited: String,
 }
 
+/// The current text of `page`, `None` if it doesn't exist.
 pub async fn get_existing_page_text(
     api: &mediawiki::api::Api,
     page: &str,
-) -> Option<(PageMeta, String)> {
+) -> anyhow::Result<Option<(PageMeta, String)>> {
     let params = api.params_into(&[
-        ("action", "parse"),
-        ("page", page),
-        ("prop", "revid|wikitext"),
-        ("formatversion", "2"), //cargo cult
+        ("action", "query"),
+        ("prop", "revisions"),
+        ("titles", page),
+        ("rvprop", "ids|timestamp|user|content"),
+        ("rvslots", "main"),
+        ("formatversion", "2"),
     ]);
-    let res = api.post_query_api_json(&params).await.unwrap();
-    let err = &res.as_object().unwrap().get("error");
-    if let Some(err) = err {
-        let code = err.as_object().unwrap()["code"].as_str().unwrap();
-        if code == "missingtitle" {
-            return None;
-        }
-        panic!("{:?}", err);
+    let res = api.post_query_api_json(&params).await?;
+    if let Some(err) = res.as_object().and_then(|o| o.get("error")) {
+        anyhow::bail!("fetching {}: {:?}", page, err);
     }
-    let text = res["parse"]["wikitext"].as_str().unwrap().to_string();
-    let revid = RevId(res["parse"]["revid"].as_i64().unwrap());
-    let pageid = PageId(res["parse"]["pageid"].as_i64().unwrap());
-    let title = res["parse"]["title"].as_str().unwrap().to_string();
-    Some((
+    let title = page;
+    let page = &res["query"]["pages"][0];
+    if page.get("missing").is_some() {
+        return Ok(None);
+    }
+    if page.get("invalid").is_some() {
+        anyhow::bail!("fetching {}: {:?}", title, page);
+    }
+    let rev = &page["revisions"][0];
+    let text = rev["slots"]["main"]["content"]
+        .as_str()
+        .with_context(|| format!("no text for {}", title))?
+        .to_string();
+    Ok(Some((
         PageMeta {
-            title,
-            revid,
-            pageid,
+            title: page["title"].as_str().unwrap().to_string(),
+            revid: RevId(rev["revid"].as_i64().unwrap()),
+            pageid: PageId(page["pageid"].as_i64().unwrap()),
+            last_editor: rev["user"].as_str().unwrap_or("").to_string(),
+            last_edited: rev["timestamp"].as_str().unwrap().to_string(),
         },
         text,
-    ))
+    )))
+}
+
+pub fn parse_timestamp(timestamp: &str) -> anyhow::Result<OffsetDateTime> {
+    OffsetDateTime::parse(timestamp, &Rfc3339)
+        .with_context(|| format!("bad timestamp {:?}", timestamp))
+}
+
+pub fn format_timestamp(t: OffsetDateTime) -> String {
+    t.replace_nanosecond(0).unwrap().format(&Rfc3339).unwrap()
+}
+
+pub async fn is_bot(api: &mediawiki::api::Api, user: &str) -> anyhow::Result<bool> {
+    let params = api.params_into(&[
+        ("action", "query"),
+        ("list", "users"),
+        ("ususers", user),
+        ("usprop", "groups"),
+        ("formatversion", "2"),
+    ]);
+    let res = api.post_query_api_json(&params).await?;
+    if let Some(err) = res.as_object().unwrap().get("error") {
+        anyhow::bail!("looking up {}: {:?}", user, err);
+    }
+    let groups = res["query"]["users"][0]["groups"].as_array();
+    Ok(groups.is_some_and(|g| g.iter().any(|g| g == "bot")))
+}
+
+/// The human who made the current revision within `window`, if any. Bots and
+/// whoever we're logged in as don't count.
+pub async fn recent_human_editor(
+    api: &mediawiki::api::Api,
+    page: &PageMeta,
+    window: time::Duration,
+) -> anyhow::Result<Option<String>> {
+    let edited = parse_timestamp(&page.last_edited)?;
+    if edited + window <= OffsetDateTime::now_utc()
+        || page.last_editor == api.user().user_name()
+        || is_bot(api, &page.last_editor).await?
+    {
+        return Ok(None);
+    }
+    Ok(Some(page.last_editor.clone()))
 }
 
 pub async fn all_pages_with_prefix(api: &mediawiki::api::Api, prefix: &str) -> Vec<String> {
@@ -74,27 +246,121 @@ pub async fn all_pages_with_prefix(api: &mediawiki::api::Api, prefix: &str) -> V
     pages
 }
 
-pub async fn edit_page(
+/// Replaces `old` with `new`, sending only the changed section when it can.
+pub async fn edit_page_changes(
+    api: &mut mediawiki::api::Api,
+    session: &mut Session,
+    page: &PageMeta,
+    old: &str,
+    new: &str,
+    summary: &str,
+    is_minor: bool,
+) -> anyhow::Result<Edited> {
+    let headings = match section_headings(api, page.revid).await {
+        Ok(headings) => headings,
+        Err(e) => {
+            println!(".. sending the whole page, no sections: {:#}", e);
+            vec![]
+        }
+    };
+    match section::single_section(old, new, &headings) {
+        Some(section) => {
+            println!(".. only section {}", section.index);
+            let summary = match &section.title {
+                Some(title) => format!("/* {} */ {}", title, summary),
+                None => summary.to_string(),
+            };
+            let section_param = section.index.to_string();
+            let mut params = edit_params(api, page, &section.text, &summary, is_minor);
+            params.extend(api.params_into(&[("section", &section_param)]));
+            submit_edit(api, session, params, new).await
+        }
+        None => edit_page(api, session, page, new, summary, is_minor).await,
+    }
+}
+
+/// The wiki's numbering of the sections of a revision, without those that come
+/// from templates.
+async fn section_headings(
+    api: &mediawiki::api::Api,
+    revid: RevId,
+) -> anyhow::Result<Vec<section::Heading>> {
+    let params = api.params_into(&[
+        ("action", "parse"),
+        ("oldid", &format!("{}", revid.0)),
+        ("prop", "sections"),
+        ("formatversion", "2"),
+    ]);
+    let res = api.post_query_api_json(&params).await?;
+    if let Some(err) = res.as_object().unwrap().get("error") {
+        anyhow::bail!("sections of revision {}: {:?}", revid.0, err);
+    }
+    // numbers come as strings, and as `T-1` for a template's
+    let number = |v: &serde_json::Value| match v {
+        serde_json::Value::String(s) => s.parse().ok(),
+        v => v.as_u64().map(|n| n as usize),
+    };
+    let sections = res["parse"]["sections"]
+        .as_array()
+        .context("no sections in the answer")?;
+    let mut headings = vec![];
+    for s in sections {
+        let (index, level, offset) = match (
+            number(&s["index"]),
+            number(&s["level"]),
+            s["byteoffset"].as_u64(),
+        ) {
+            (Some(index), Some(level), Some(offset)) => (index, level, offset as usize),
+            _ => continue,
+        };
+        headings.push(section::Heading {
+            index,
+            level,
+            offset,
+        });
+    }
+    Ok(headings)
+}
+
+fn edit_params(
     api: &mediawiki::api::Api,
-    token: &str,
     page: &PageMeta,
     content: &str,
     summary: &str,
     is_minor: bool,
-) -> anyhow::Result<()> {
+) -> std::collections::HashMap<String, String> {
     let mut params = api.params_into(&[
         ("action", "edit"),
         ("title", page.title.as_str()),
         ("text", content),
         ("summary", summary),
         ("baserevid", &format!("{}", page.revid.0)),
-        ("token", token), // must be last
     ]);
     if is_minor {
         params.extend(api.params_into(&[("minor", &format!("{}", is_minor))]));
     }
+    params
+}
 
-    let res = api.post_query_api_json(&params).await.unwrap();
+pub async fn edit_page(
+    api: &mut mediawiki::api::Api,
+    session: &mut Session,
+    page: &PageMeta,
+    content: &str,
+    summary: &str,
+    is_minor: bool,
+) -> anyhow::Result<Edited> {
+    let params = edit_params(api, page, content, summary, is_minor);
+    submit_edit(api, session, params, content).await
+}
+
+async fn submit_edit(
+    api: &mut mediawiki::api::Api,
+    session: &mut Session,
+    params: std::collections::HashMap<String, String>,
+    expected: &str,
+) -> anyhow::Result<Edited> {
+    let res = post_edit(api, session, params).await?;
     // println!("result: {:?}", res);
 
     // Object({"edit": Object({"contentmodel": String("wikitext"), "nochange": String(""), "pageid": Number(25544), "result": String("Success"), "title": String("GGST/Anji")})})
@@ -106,5 +372,467 @@ pub async fn edit_page(
         println!("error {:?}", err);
         anyhow::bail!("failed to edit: {:?}", err);
     }
-    Ok(())
+    check_edit(api, &res["edit"], expected).await
+}
+
+/// How an edit went, when it didn't fail outright.
+#[derive(Debug)]
+pub enum Edited {
+    /// The page already said that.
+    NoChange,
+    Saved(RevId),
+    /// Saved, but the wiki changed the text on the way in (signatures, `subst:`,
+    /// the pipe trick, ...). `line` is the first one that differs.
+    Mismatch {
+        revid: RevId,
+        line: usize,
+    },
+}
+
+/// Reads the `edit` part of the response, then fetches the saved revision and
+/// compares it with `expected`, the whole page we meant to end up with.
+async fn check_edit(
+    api: &mediawiki::api::Api,
+    edit: &serde_json::Value,
+    expected: &str,
+) -> anyhow::Result<Edited> {
+    // captchas and AbuseFilter warnings come back as a non-Success result, not an error
+    if edit["result"] != "Success" {
+        anyhow::bail!("edit not saved: {}", edit);
+    }
+    if edit.get("nochange").is_some() {
+        return Ok(Edited::NoChange);
+    }
+    let revid = RevId(
+        edit["newrevid"]
+            .as_i64()
+            .with_context(|| format!("no newrevid in {}", edit))?,
+    );
+    let saved = get_revision_text(api, revid).await?;
+    // the wiki drops trailing whitespace on save
+    let (saved, expected) = (saved.trim_end(), expected.trim_end());
+    if saved == expected {
+        return Ok(Edited::Saved(revid));
+    }
+    let line = saved
+        .lines()
+        .zip(expected.lines())
+        .take_while(|(a, b)| a == b)
+        .count()
+        + 1;
+    Ok(Edited::Mismatch { revid, line })
+}
+
+pub async fn get_revision_text(api: &mediawiki::api::Api, revid: RevId) -> anyhow::Result<String> {
+    let params = api.params_into(&[
+        ("action", "query"),
+        ("prop", "revisions"),
+        ("revids", &format!("{}", revid.0)),
+        ("rvprop", "content"),
+        ("rvslots", "main"),
+        ("formatversion", "2"),
+    ]);
+    let res = api.post_query_api_json(&params).await?;
+    if let Some(err) = res.as_object().unwrap().get("error") {
+        anyhow::bail!("fetching revision {}: {:?}", revid.0, err);
+    }
+    res["query"]["pages"][0]["revisions"][0]["slots"]["main"]["content"]
+        .as_str()
+        .map(str::to_string)
+        .with_context(|| format!("no revision {}", revid.0))
+}
+
+/// Saves `content` to `title` unless it already says that, creating the page if
+/// needed.
+pub async fn save_page(
+    api: &mut mediawiki::api::Api,
+    session: &mut Session,
+    title: &str,
+    content: &str,
+    summary: &str,
+) -> anyhow::Result<Edited> {
+    match get_existing_page_text(api, title).await? {
+        // the wiki drops trailing whitespace on save
+        Some((_, text)) if text.trim_end() == content.trim_end() => return Ok(Edited::NoChange),
+        Some((meta, _)) => return edit_page(api, session, &meta, content, summary, false).await,
+        None => {}
+    }
+    let params = api.params_into(&[
+        ("action", "edit"),
+        ("title", title),
+        ("text", content),
+        ("summary", summary),
+        ("createonly", "1"),
+    ]);
+    let res = post_edit(api, session, params).await?;
+    if let Some(err) = res.as_object().unwrap().get("error") {
+        anyhow::bail!("failed to create {}: {:?}", title, err);
+    }
+    check_edit(api, &res["edit"], content).await
+}
+
+/// Redirect pages under `prefix` in the main namespace, with their text.
+pub async fn redirect_pages(
+    api: &mediawiki::api::Api,
+    prefix: &str,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let mut params = api.params_into(&[
+        ("action", "query"),
+        ("generator", "allpages"),
+        ("gapprefix", prefix),
+        ("gapfilterredir", "redirects"),
+        // the most pages `rvprop=content` takes at once
+        ("gaplimit", "50"),
+        ("prop", "revisions"),
+        ("rvprop", "content"),
+        ("rvslots", "main"),
+        ("formatversion", "2"),
+    ]);
+    let mut pages = vec![];
+    loop {
+        let res = api.post_query_api_json(&params).await?;
+        if let Some(err) = res.as_object().unwrap().get("error") {
+            anyhow::bail!("listing redirects: {:?}", err);
+        }
+        for page in res["query"]["pages"].as_array().into_iter().flatten() {
+            let text = page["revisions"][0]["slots"]["main"]["content"].as_str();
+            if let (Some(title), Some(text)) = (page["title"].as_str(), text) {
+                pages.push((title.to_string(), text.to_string()));
+            }
+        }
+        match res.get("continue").and_then(|c| c.as_object()) {
+            Some(cont) => {
+                for (key, value) in cont {
+                    let value = value.as_str().map_or(value.to_string(), str::to_string);
+                    params.insert(key.clone(), value);
+                }
+            }
+            None => break,
+        }
+    }
+    Ok(pages)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageStatus {
+    Missing,
+    Redirect,
+    Page,
+}
+
+/// Whether each of `titles` exists and is a redirect, keyed by the titles as
+/// given.
+pub async fn page_status(
+    api: &mediawiki::api::Api,
+    titles: &[String],
+) -> anyhow::Result<std::collections::BTreeMap<String, PageStatus>> {
+    let mut out = std::collections::BTreeMap::new();
+    for batch in titles.chunks(50) {
+        let params = api.params_into(&[
+            ("action", "query"),
+            ("prop", "info"),
+            ("titles", &batch.join("|")),
+            ("formatversion", "2"),
+        ]);
+        let res = api.post_query_api_json(&params).await?;
+        if let Some(err) = res.as_object().unwrap().get("error") {
+            anyhow::bail!("looking up {} page(s): {:?}", batch.len(), err);
+        }
+        let query = &res["query"];
+        let mut statuses = std::collections::BTreeMap::new();
+        for page in query["pages"].as_array().into_iter().flatten() {
+            let status = if page["missing"] == true || page["invalid"] == true {
+                PageStatus::Missing
+            } else if page["redirect"] == true {
+                PageStatus::Redirect
+            } else {
+                PageStatus::Page
+            };
+            statuses.insert(page["title"].as_str().unwrap_or("").to_string(), status);
+        }
+        // the wiki answers with its own spelling of the titles
+        let normalized = query["normalized"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|n| Some((n["from"].as_str()?, n["to"].as_str()?)))
+            .collect::<std::collections::BTreeMap<_, _>>();
+        for title in batch {
+            let key = normalized.get(title.as_str()).copied().unwrap_or(title);
+            if let Some(status) = statuses.get(key) {
+                out.insert(title.clone(), *status);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Where a link to some title ends up.
+#[derive(Debug, Clone)]
+pub struct Landing {
+    /// After following redirects.
+    pub title: String,
+    /// Of the first redirect that had one.
+    pub fragment: Option<String>,
+    pub redirected: bool,
+    pub exists: bool,
+}
+
+/// Where links to each of `titles` end up, keyed by the titles as given.
+/// Interwiki links and the like are left out.
+pub async fn link_landings(
+    api: &mediawiki::api::Api,
+    titles: &[String],
+) -> anyhow::Result<std::collections::BTreeMap<String, Landing>> {
+    let mut out = std::collections::BTreeMap::new();
+    for batch in titles.chunks(50) {
+        let params = api.params_into(&[
+            ("action", "query"),
+            ("titles", &batch.join("|")),
+            ("redirects", "1"),
+            ("formatversion", "2"),
+        ]);
+        let res = api.post_query_api_json(&params).await?;
+        if let Some(err) = res.as_object().unwrap().get("error") {
+            anyhow::bail!("looking up {} page(s): {:?}", batch.len(), err);
+        }
+        let query = &res["query"];
+        let pairs = |key: &str| {
+            query[key]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|n| Some((n["from"].as_str()?, n)))
+                .collect::<std::collections::BTreeMap<_, _>>()
+        };
+        let normalized = pairs("normalized");
+        let redirects = pairs("redirects");
+        let pages = query["pages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|p| Some((p["title"].as_str()?, p)))
+            .collect::<std::collections::BTreeMap<_, _>>();
+        for title in batch {
+            let mut current = normalized
+                .get(title.as_str())
+                .and_then(|n| n["to"].as_str())
+                .unwrap_or(title);
+            let mut fragment = None;
+            let mut hops = 0;
+            while let Some(r) = redirects.get(current) {
+                fragment = fragment.or(r["tofragment"].as_str());
+                current = r["to"].as_str().unwrap_or(current);
+                hops += 1;
+                // the wiki stops at loops, so should we
+                if hops > redirects.len() {
+                    break;
+                }
+            }
+            let page = match pages.get(current) {
+                Some(page) => page,
+                None => continue,
+            };
+            let missing = page["missing"] == true || page["invalid"] == true;
+            out.insert(
+                title.clone(),
+                Landing {
+                    title: current.to_string(),
+                    fragment: fragment.map(str::to_string),
+                    redirected: hops > 0,
+                    exists: !missing || page["special"] == true,
+                },
+            );
+        }
+    }
+    Ok(out)
+}
+
+/// Current text of each of `titles` that exists, by title.
+pub async fn page_texts(
+    api: &mediawiki::api::Api,
+    titles: &[String],
+) -> anyhow::Result<std::collections::BTreeMap<String, String>> {
+    let mut out = std::collections::BTreeMap::new();
+    for batch in titles.chunks(50) {
+        let params = api.params_into(&[
+            ("action", "query"),
+            ("prop", "revisions"),
+            ("titles", &batch.join("|")),
+            ("rvprop", "content"),
+            ("rvslots", "main"),
+            ("formatversion", "2"),
+        ]);
+        let res = api.post_query_api_json(&params).await?;
+        if let Some(err) = res.as_object().unwrap().get("error") {
+            anyhow::bail!("fetching {} page(s): {:?}", batch.len(), err);
+        }
+        for page in res["query"]["pages"].as_array().into_iter().flatten() {
+            let text = page["revisions"][0]["slots"]["main"]["content"].as_str();
+            if let (Some(title), Some(text)) = (page["title"].as_str(), text) {
+                out.insert(title.to_string(), text.to_string());
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// What `action=move` takes along besides the page.
+pub struct MoveOptions {
+    /// Leave a redirect behind.
+    pub redirect: bool,
+    pub talk: bool,
+    pub subpages: bool,
+}
+
+/// Moves `from` to `to`, returning every title that moved with where it went:
+/// the page, then its talk page and subpages if asked for.
+pub async fn move_page(
+    api: &mut mediawiki::api::Api,
+    session: &mut Session,
+    from: &str,
+    to: &str,
+    reason: &str,
+    options: &MoveOptions,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let mut params = api.params_into(&[
+        ("action", "move"),
+        ("from", from),
+        ("to", to),
+        ("reason", reason),
+        ("formatversion", "2"),
+    ]);
+    for (flag, set) in [
+        ("noredirect", !options.redirect),
+        ("movetalk", options.talk),
+        ("movesubpages", options.subpages),
+    ] {
+        if set {
+            params.extend(api.params_into(&[(flag, "1")]));
+        }
+    }
+    let res = post_edit(api, session, params).await?;
+    if let Some(err) = res.as_object().unwrap().get("error") {
+        anyhow::bail!("failed to move {} to {}: {:?}", from, to, err);
+    }
+    let moved = &res["move"];
+    let mut out = vec![(from.to_string(), to.to_string())];
+    if let (Some(from), Some(to)) = (moved["talkfrom"].as_str(), moved["talkto"].as_str()) {
+        out.push((from.to_string(), to.to_string()));
+    }
+    for key in ["subpages", "subpages-talk"] {
+        // failed ones come back with an `errors` list instead
+        for sub in moved[key].as_array().into_iter().flatten() {
+            match (sub["from"].as_str(), sub["to"].as_str()) {
+                (Some(from), Some(to)) => out.push((from.to_string(), to.to_string())),
+                _ => println!("failed to move a subpage: {}", sub),
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Pages linking to `title`: only redirects if `redirects`, otherwise everything
+/// else.
+pub async fn backlinks(
+    api: &mediawiki::api::Api,
+    title: &str,
+    redirects: bool,
+) -> anyhow::Result<Vec<String>> {
+    let filter = if redirects {
+        "redirects"
+    } else {
+        "nonredirects"
+    };
+    let mut params = api.params_into(&[
+        ("action", "query"),
+        ("list", "backlinks"),
+        ("bltitle", title),
+        ("blfilterredir", filter),
+        ("bllimit", "max"),
+    ]);
+    let mut pages = vec![];
+    loop {
+        let res = api.post_query_api_json(&params).await?;
+        if let Some(err) = res.as_object().unwrap().get("error") {
+            anyhow::bail!("listing links to {}: {:?}", title, err);
+        }
+        for bl in res["query"]["backlinks"].as_array().unwrap() {
+            pages.push(bl["title"].as_str().unwrap().to_string());
+        }
+        match res.get("continue").and_then(|c| c["blcontinue"].as_str()) {
+            Some(cont) => params.extend(api.params_into(&[("blcontinue", cont)])),
+            None => break,
+        }
+    }
+    Ok(pages)
+}
+
+/// One entry of `list=recentchanges`.
+#[derive(Debug, Clone)]
+pub struct RecentChange {
+    pub rcid: i64,
+    pub title: String,
+    pub timestamp: String,
+    pub user: String,
+    pub bot: bool,
+}
+
+/// Edits and page creations at or after `since`, oldest first.
+pub async fn recent_changes(
+    api: &mediawiki::api::Api,
+    since: &str,
+    namespace: Option<i64>,
+) -> anyhow::Result<Vec<RecentChange>> {
+    let mut params = api.params_into(&[
+        ("action", "query"),
+        ("list", "recentchanges"),
+        ("rcprop", "ids|title|timestamp|user|flags"),
+        ("rctype", "edit|new"),
+        ("rcdir", "newer"),
+        ("rcstart", since),
+        ("rclimit", "max"),
+    ]);
+    if let Some(ns) = namespace {
+        params.extend(api.params_into(&[("rcnamespace", &ns.to_string())]));
+    }
+    let mut changes = vec![];
+    loop {
+        let res = api.post_query_api_json(&params).await?;
+        if let Some(err) = res.as_object().unwrap().get("error") {
+            anyhow::bail!("listing recent changes: {:?}", err);
+        }
+        for rc in res["query"]["recentchanges"].as_array().unwrap() {
+            changes.push(RecentChange {
+                rcid: rc["rcid"].as_i64().context("no rcid")?,
+                title: rc["title"].as_str().unwrap().to_string(),
+                timestamp: rc["timestamp"].as_str().unwrap().to_string(),
+                user: rc["user"].as_str().unwrap_or("").to_string(),
+                bot: rc.get("bot").is_some(),
+            });
+        }
+        match res.get("continue").and_then(|c| c["rccontinue"].as_str()) {
+            Some(cont) => params.extend(api.params_into(&[("rccontinue", cont)])),
+            None => break,
+        }
+    }
+    Ok(changes)
+}
+
+/// Timestamp of the latest edit or page creation on the wiki.
+pub async fn newest_change(api: &mediawiki::api::Api) -> anyhow::Result<Option<String>> {
+    let params = api.params_into(&[
+        ("action", "query"),
+        ("list", "recentchanges"),
+        ("rcprop", "timestamp"),
+        ("rctype", "edit|new"),
+        ("rclimit", "1"),
+    ]);
+    let res = api.post_query_api_json(&params).await?;
+    if let Some(err) = res.as_object().unwrap().get("error") {
+        anyhow::bail!("listing recent changes: {:?}", err);
+    }
+    Ok(res["query"]["recentchanges"][0]["timestamp"]
+        .as_str()
+        .map(str::to_string))
 }
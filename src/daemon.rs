@@ -0,0 +1,341 @@
+//! `--mode daemon`: keeps polling recent changes and, once humans have left a
+//! page alone for the quiet period, runs the transforms over it.
+//!
+//! Only the transforms in `SAFE_TRANSFORMS` may edit unattended, and for now
+//! there are none: `color` still refuses `--apply`. Until one is added the
+//! daemon only does dry runs, recording what it would change in the status file
+//! and dumping the would-be text under `out/daemon/`.
+//!
+//! Everything it needs to pick up where it left off is in the state file. The
+//! status file is rewritten after every poll for whoever wants to look. All wiki
+//! traffic goes through the profile, so `--profile local` with `--once` runs a
+//! single poll against a local stand-in.
+
+use crate::api::{self, Session};
+use crate::recent;
+use crate::transform::{self, read_json5, Prepared};
+use crate::Runner;
+use anyhow::Context;
+use std::collections::BTreeMap;
+use time::OffsetDateTime;
+
+/// Transforms that are fine to edit with unattended, with `--apply`. Config-driven
+/// ones are not, and neither is one that refuses to apply.
+const SAFE_TRANSFORMS: &[&str] = &[];
+
+/// How many events the status file remembers.
+const STATUS_EVENTS: usize = 50;
+
+/// The `--config` of the daemon.
+#[derive(serde::Deserialize)]
+pub struct DaemonConfig {
+    /// Seconds between polls.
+    poll_secs: u64,
+    /// Seconds since the last human edit before a page is touched.
+    quiet_secs: i64,
+    /// Run in this order, each on the previous one's output.
+    transforms: Vec<String>,
+    #[serde(default)]
+    prefix: Option<String>,
+    #[serde(default)]
+    namespace: Option<i64>,
+    state: String,
+    status: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+struct State {
+    /// Newest change seen.
+    last_timestamp: Option<String>,
+    /// The changes at `last_timestamp`, which the next poll gets again.
+    #[serde(default)]
+    seen: Vec<i64>,
+    /// Pages waiting for their quiet period, with their last human edit.
+    pending: BTreeMap<String, String>,
+}
+
+#[derive(serde::Serialize)]
+struct Status<'a> {
+    updated: String,
+    polls: u64,
+    apply: bool,
+    last_timestamp: Option<&'a str>,
+    pending: &'a BTreeMap<String, String>,
+    /// Newest last.
+    events: &'a [Event],
+}
+
+#[derive(serde::Serialize, Clone)]
+struct Event {
+    at: String,
+    /// Empty for a poll that failed.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    page: String,
+    /// `edited`, `unchanged`, `dry-run`, `deferred`, `mismatch`, `failed`,
+    /// `missing` or `skipped`
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    message: String,
+}
+
+pub struct Daemon {
+    config: DaemonConfig,
+    transforms: Vec<Box<dyn Prepared>>,
+    apply: bool,
+    state: State,
+    polls: u64,
+    events: Vec<Event>,
+}
+
+fn now() -> OffsetDateTime {
+    OffsetDateTime::now_utc()
+}
+
+fn write_json(path: &str, value: &impl serde::Serialize) -> anyhow::Result<()> {
+    let path = std::path::Path::new(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(value)?)
+        .with_context(|| format!("writing {}", path.display()))
+}
+
+impl Daemon {
+    pub fn load(config_path: &str, apply: bool) -> anyhow::Result<Self> {
+        let config: DaemonConfig = read_json5(config_path)?;
+        let mut transforms = vec![];
+        for name in &config.transforms {
+            if apply && !SAFE_TRANSFORMS.contains(&name.as_str()) {
+                anyhow::bail!(
+                    "{:?} is not allowed to edit unattended; leave out --apply for a dry run",
+                    name
+                );
+            }
+            let entry =
+                transform::find(name).with_context(|| format!("no transform {:?}", name))?;
+            let prepared = entry.prepare(None)?;
+            if let (true, Some(reason)) = (apply, prepared.job().refuse_apply) {
+                anyhow::bail!("{} refuses to --apply: {}", name, reason);
+            }
+            transforms.push(prepared);
+        }
+        let state = match std::path::Path::new(&config.state).exists() {
+            true => read_json5(&config.state)?,
+            false => State::default(),
+        };
+        Ok(Self {
+            config,
+            transforms,
+            apply,
+            state,
+            polls: 0,
+            events: vec![],
+        })
+    }
+
+    /// Polls forever, or once. A poll that fails is retried the next time;
+    /// only failing to write the state or status files stops it.
+    pub async fn run(
+        &mut self,
+        api: &mut mediawiki::api::Api,
+        session: &mut Session,
+        runner: &mut Runner,
+        once: bool,
+    ) -> anyhow::Result<()> {
+        loop {
+            if let Err(e) = self.poll(api, session, runner).await {
+                println!("poll {} failed: {:#}", self.polls, e);
+                self.events.push(Event {
+                    at: api::format_timestamp(now()),
+                    page: String::new(),
+                    outcome: "failed",
+                    message: format!("poll {}: {:#}", self.polls, e),
+                });
+            }
+            self.save()?;
+            if once {
+                return Ok(());
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(self.config.poll_secs)).await;
+        }
+    }
+
+    async fn poll(
+        &mut self,
+        api: &mut mediawiki::api::Api,
+        session: &mut Session,
+        runner: &mut Runner,
+    ) -> anyhow::Result<()> {
+        self.polls += 1;
+        // they're printed as they come, and would pile up forever otherwise
+        runner.diagnostics.clear();
+        self.collect(api).await?;
+
+        let quiet = time::Duration::seconds(self.config.quiet_secs);
+        let now = now();
+        let mut due = vec![];
+        for (title, last_human) in &self.state.pending {
+            if api::parse_timestamp(last_human)? + quiet <= now {
+                due.push(title.clone());
+            }
+        }
+        println!(
+            "poll {}: {} pending, {} due",
+            self.polls,
+            self.state.pending.len(),
+            due.len()
+        );
+        for title in due {
+            self.state.pending.remove(&title);
+            let (event, requeue) = self.process(api, session, runner, &title).await;
+            println!("{}: {} {}", event.page, event.outcome, event.message);
+            self.events.push(event);
+            if let Some(last_human) = requeue {
+                self.state.pending.insert(title, last_human);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the state and status files.
+    fn save(&mut self) -> anyhow::Result<()> {
+        let overflow = self.events.len().saturating_sub(STATUS_EVENTS);
+        self.events.drain(..overflow);
+
+        write_json(&self.config.state, &self.state)?;
+        write_json(
+            &self.config.status,
+            &Status {
+                updated: api::format_timestamp(now()),
+                polls: self.polls,
+                apply: self.apply,
+                last_timestamp: self.state.last_timestamp.as_deref(),
+                pending: &self.state.pending,
+                events: &self.events,
+            },
+        )
+    }
+
+    /// Queues pages humans edited since the last poll.
+    async fn collect(&mut self, api: &mediawiki::api::Api) -> anyhow::Result<()> {
+        let filter = recent::Filter {
+            prefix: self.config.prefix.as_deref(),
+            namespace: self.config.namespace,
+        };
+        let since = match &self.state.last_timestamp {
+            Some(s) => s.clone(),
+            None => {
+                println!("no timestamp yet, starting from now");
+                let (last_timestamp, seen) = recent::start(api, filter.namespace).await?;
+                self.state.last_timestamp = last_timestamp;
+                self.state.seen = seen;
+                return Ok(());
+            }
+        };
+        let changes = api::recent_changes(api, &since, filter.namespace).await?;
+        let me = api.user().user_name();
+        for change in &changes {
+            // rcstart is inclusive, so the changes at `since` come again
+            if self.state.seen.contains(&change.rcid) {
+                continue;
+            }
+            if change.bot || change.user == me || !filter.matches(change) {
+                continue;
+            }
+            let last = self.state.pending.entry(change.title.clone()).or_default();
+            if *last < change.timestamp {
+                *last = change.timestamp.clone();
+            }
+        }
+        let (last_timestamp, seen) = recent::newest(&changes, &since, &self.state.seen);
+        self.state.last_timestamp = Some(last_timestamp);
+        self.state.seen = seen;
+        Ok(())
+    }
+
+    /// What happened, and when the last human edit was if the page has to wait
+    /// some more.
+    async fn process(
+        &self,
+        api: &mut mediawiki::api::Api,
+        session: &mut Session,
+        runner: &mut Runner,
+        title: &str,
+    ) -> (Event, Option<String>) {
+        let event = |outcome, message: String| Event {
+            at: api::format_timestamp(now()),
+            page: title.to_string(),
+            outcome,
+            message,
+        };
+        // the transforms' own page lists, skip lists included
+        let mut transforms = vec![];
+        for t in &self.transforms {
+            let wanted = match t.job().pages {
+                Some(pages) => pages.matches(title),
+                None => Ok(true),
+            };
+            match wanted {
+                Ok(true) => transforms.push(t),
+                Ok(false) => {}
+                Err(e) => return (event("failed", format!("{}: {}", t.name(), e)), None),
+            }
+        }
+        if transforms.is_empty() {
+            return (
+                event("skipped", "not a page of any transform".to_string()),
+                None,
+            );
+        }
+        let (meta, content) = match api::get_existing_page_text(api, title).await {
+            Ok(Some(p)) => p,
+            Ok(None) => return (event("missing", String::new()), None),
+            Err(e) => return (event("failed", format!("{:#}", e)), None),
+        };
+        // someone may have come back since the change that queued it
+        let quiet = time::Duration::seconds(self.config.quiet_secs);
+        match api::recent_human_editor(api, &meta, quiet).await {
+            Ok(Some(editor)) => {
+                let message = format!("edited by {} at {}", editor, meta.last_edited);
+                return (event("deferred", message), Some(meta.last_edited));
+            }
+            Ok(None) => {}
+            Err(e) => return (event("failed", e.to_string()), None),
+        }
+        let mut text = content.clone();
+        let mut summaries = vec![];
+        let mut minor = true;
+        for t in transforms {
+            let new_text = match t.transform(title, &text, runner) {
+                Ok(new_text) => new_text,
+                Err(e) => return (event("failed", format!("{}: {}", t.name(), e)), None),
+            };
+            if new_text != text {
+                summaries.push(t.summary());
+                minor &= t.job().minor;
+                text = new_text;
+            }
+        }
+        if text == content {
+            return (event("unchanged", String::new()), None);
+        }
+        crate::dump_file("daemon", title, &text);
+        let summary = summaries.join("; ");
+        if !self.apply {
+            return (event("dry-run", summary), None);
+        }
+        let outcome =
+            match api::edit_page_changes(api, session, &meta, &content, &text, &summary, minor)
+                .await
+            {
+                Ok(api::Edited::Saved(_)) => event("edited", summary),
+                Ok(api::Edited::NoChange) => event("unchanged", String::new()),
+                Ok(api::Edited::Mismatch { revid, line }) => event(
+                    "mismatch",
+                    format!("{:?} differs from what was sent at line {}", revid, line),
+                ),
+                Err(e) => event("failed", e.to_string()),
+            };
+        (outcome, None)
+    }
+}
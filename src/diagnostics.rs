@@ -0,0 +1,160 @@
+use crate::warning_policy::{ParseWarning, WarningAction};
+
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: String,
+    pub page: String,
+    pub span: std::ops::Range<usize>,
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in chars
+    pub column: usize,
+    /// The source line the span starts on.
+    pub snippet: String,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(
+        severity: Severity,
+        code: &str,
+        span: std::ops::Range<usize>,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            severity,
+            code: code.to_string(),
+            page: String::new(),
+            span,
+            line: 0,
+            column: 0,
+            snippet: String::new(),
+            message: message.into(),
+        }
+    }
+
+    /// Fills in page, line:column and snippet. Visitors only know the span.
+    pub fn locate(&mut self, page: &str, index: &LineIndex, text: &str) {
+        self.page = page.to_string();
+        let (line, column) = index.line_col(text, self.span.start);
+        self.line = line;
+        self.column = column;
+        let line_text = index.line_text(text, line);
+        self.snippet = truncate(line_text.trim_end(), 200).to_string();
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = format!(
+            "{}:{}:{}: {}[{}]: {}",
+            self.page, self.line, self.column, self.severity, self.code, self.message
+        );
+        if !self.snippet.is_empty() {
+            out += &format!("\n    | {}", self.snippet);
+        }
+        out
+    }
+}
+
+impl From<&ParseWarning> for Diagnostic {
+    fn from(w: &ParseWarning) -> Self {
+        let severity = match w.action {
+            WarningAction::Ignore => Severity::Info,
+            WarningAction::Warn => Severity::Warning,
+            WarningAction::Fail => Severity::Error,
+        };
+        Diagnostic::new(
+            severity,
+            &format!("parse/{}", w.kind),
+            w.range.clone(),
+            format!("{} {:?}", w.description, w.text),
+        )
+    }
+}
+
+fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut cut = max;
+    while !s.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    &s[..cut]
+}
+
+/// Byte offsets of line starts, for turning spans into line:column.
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        Self { starts }
+    }
+    pub fn line_col(&self, text: &str, offset: usize) -> (usize, usize) {
+        let offset = offset.min(text.len());
+        let line = self.starts.partition_point(|s| *s <= offset) - 1;
+        let column = text[self.starts[line]..]
+            .char_indices()
+            .take_while(|(i, _)| self.starts[line] + i < offset)
+            .count();
+        (line + 1, column + 1)
+    }
+    /// `line` is 1-based.
+    pub fn line_text<'a>(&self, text: &'a str, line: usize) -> &'a str {
+        let start = self.starts[line - 1];
+        let end = self.starts.get(line).map(|e| e - 1).unwrap_or(text.len());
+        &text[start..end]
+    }
+}
+
+/// Where visitors put their findings. `transform_text` drains it after the walk.
+#[derive(Default)]
+pub struct DiagnosticSink {
+    diagnostics: Vec<Diagnostic>,
+}
+impl DiagnosticSink {
+    pub fn emit(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+    pub fn error(&mut self, code: &str, span: std::ops::Range<usize>, message: impl Into<String>) {
+        self.emit(Diagnostic::new(Severity::Error, code, span, message));
+    }
+    pub fn take(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+}
+
+pub fn print_diagnostics(diagnostics: &[Diagnostic]) {
+    for d in diagnostics {
+        println!("{}", d.render());
+    }
+}
+
+pub fn write_json(path: &str, diagnostics: &[Diagnostic]) -> anyhow::Result<()> {
+    let path = std::path::Path::new(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(diagnostics)?)?;
+    Ok(())
+}
@@ -1,45 +1,93 @@
 mod prelude;
 
 mod api;
+mod daemon;
+mod diagnostics;
+mod links;
+mod lint;
 mod parse;
-mod parse_ext_traits;
+pub mod parse_ext_traits;
+mod profile;
+mod recent;
+mod redirects;
+mod section;
+pub mod selector;
+pub mod semantic_diff;
+pub mod table_grid;
+pub mod template_view;
+mod transform;
+mod verify;
 mod visitors;
+mod warning_policy;
 
 use anyhow::Context;
 
-const WIKI_URL: &str = "https://www.dustloop.com/wiki/api.php";
-
-#[derive(serde::Deserialize)]
-struct Cred {
-    name: String,
-    password: String,
-}
-
-fn colorize_page(title: &str, existing_text: &str) -> anyhow::Result<String> {
-    let config: visitors::ColorConfig;
-    let config_file = match title {
-        t if t.starts_with("GGST/") => "data/color/ggst.json5",
-        t if t.starts_with("GGACR/") => "data/color/ggacr.json5",
-        _ => panic!("{:?}", title),
-    };
-
-    config = json5::from_str(&std::fs::read_to_string(config_file).unwrap()).unwrap();
-    let mut visitor = visitors::ColorVisitor::new(config);
-    let skip_errors = ["GGACR/Venom/Combos"].contains(&title);
-    let out = parse::transform_text(existing_text, &mut visitor, skip_errors);
-    out
+/// Per-run settings, and the diagnostics collected so far.
+struct Runner {
+    policy: WarningPolicy,
+    /// Re-parse and re-run every result, see `verify::verified_transform`.
+    verify: bool,
+    /// Print a structural diff of every changed page.
+    semantic_diff: bool,
+    diagnostics: Vec<Diagnostic>,
 }
-
-fn templatize_combo(existing_text: &str) -> anyhow::Result<String> {
-    let mut visitor = visitors::ComboTableVisitor::new();
-    let out = parse::transform_text(existing_text, &mut visitor, false);
-    out
+impl Runner {
+    /// Transforms one page, printing whatever diagnostics it produced.
+    fn transform<T: transform::Transform>(
+        &mut self,
+        transform: &T,
+        title: &str,
+        existing_text: &str,
+    ) -> anyhow::Result<String> {
+        let first_new = self.diagnostics.len();
+        let out = if self.verify {
+            verify::verified_transform(
+                transform,
+                title,
+                existing_text,
+                &self.policy,
+                &mut self.diagnostics,
+            )
+        } else {
+            transform
+                .visitor(title, existing_text)
+                .and_then(|mut visitor| {
+                    parse::transform_text(
+                        title,
+                        existing_text,
+                        &mut visitor,
+                        &self.policy,
+                        &mut self.diagnostics,
+                    )
+                })
+        };
+        diagnostics::print_diagnostics(&self.diagnostics[first_new..]);
+        if let (true, Ok(new_text)) = (self.semantic_diff, &out) {
+            for change in semantic_diff::diff(existing_text, new_text) {
+                println!("  {}", change.render());
+            }
+        }
+        out
+    }
 }
 
-fn find_n_replace(existing_text: &str, config: &FindReplaceConfig) -> anyhow::Result<String> {
-    let mut visitor = visitors::FindReplaceVisitor::new(config);
-    let out = parse::transform_text(existing_text, &mut visitor, false);
-    out
+/// One `page:line:col: kind: snippet` line per element matching the selector.
+fn query_page(title: &str, text: &str, selector: &selector::Selector) -> Vec<String> {
+    let parsed = parse::parse_text(text);
+    let tree = selector::Tree::new(&parsed.nodes, text);
+    let index = diagnostics::LineIndex::new(text);
+    tree.select(selector)
+        .into_iter()
+        .map(|e| {
+            let (line, column) = index.line_col(text, e.range.start);
+            let snippet = text[e.range.clone()].lines().next().unwrap_or("");
+            let snippet = match snippet.char_indices().nth(120) {
+                Some((cut, _)) => format!("{}...", &snippet[..cut]),
+                None => snippet.to_string(),
+            };
+            format!("{}:{}:{}: {}: {}", title, line, column, e.kind, snippet)
+        })
+        .collect()
 }
 
 fn dump_file(cat: &str, file: &str, content: &str) {
@@ -54,7 +102,8 @@ fn dump_file(cat: &str, file: &str, content: &str) {
 use std::io::Write;
 
 use clap::Parser;
-use visitors::FindReplaceConfig;
+use diagnostics::Diagnostic;
+use warning_policy::WarningPolicy;
 #[derive(clap::Parser, Debug)]
 struct Args {
     #[clap(long)]
@@ -67,176 +116,530 @@ struct Args {
     config: Option<String>,
     #[clap(long)]
     page: Option<String>,
-}
 
-#[derive(serde::Deserialize)]
-struct SkipConfig {
-    skip_pages: Vec<String>,
-}
+    /// json5 file describing which parser warnings to ignore, print or fail on
+    #[clap(long, default_value = "data/parse_warnings.json5")]
+    warning_policy: String,
 
-async fn get_edit_token(api: &mut mediawiki::api::Api) -> String {
-    let creds_path = std::path::Path::new("bot-creds.json5");
-    if !creds_path.exists() {
-        println!("{:?} does not exist. Editing will not work", creds_path);
-        return String::new();
-    }
-    let cred: Cred = json5::from_str(&std::fs::read_to_string(creds_path).unwrap()).unwrap();
-    api.login(cred.name, cred.password).await.unwrap();
-    return api.get_edit_token().await.unwrap();
+    /// Also write all diagnostics of the run to this file as JSON
+    #[clap(long)]
+    diagnostics_json: Option<String>,
+
+    /// Check that untouched parts of each page survive and that a second pass changes nothing
+    #[clap(long)]
+    verify: bool,
+
+    /// Selector for `--mode query`, see `selector`
+    #[clap(long)]
+    query: Option<String>,
+
+    /// Pages to go over, for modes that take any page. Redirects to look at for `--mode redirects`
+    #[clap(long)]
+    prefix: Option<String>,
+
+    /// Print what changed on each page in terms of templates, tables, headings and links
+    #[clap(long)]
+    semantic_diff: bool,
+
+    /// Comma separated lints for `--mode lint`, all of them by default
+    #[clap(long)]
+    lints: Option<String>,
+
+    /// How `--mode lint` reports: text, json or wikitext
+    #[clap(long, default_value = "text")]
+    lint_format: String,
+
+    /// Write the lint report to this file instead of stdout
+    #[clap(long)]
+    lint_output: Option<String>,
+
+    /// Also save the lint or dry-run report as a wikitable to this wiki page
+    #[clap(long)]
+    publish: Option<String>,
+
+    /// Which wiki to work on, see `--profiles`
+    #[clap(long, default_value = "live")]
+    profile: String,
+
+    /// json5 file with the wiki profiles
+    #[clap(long, default_value = "data/profiles.json5")]
+    profiles: String,
+
+    /// json5 file with the bot's `name` and `password`, overriding the environment and the profile
+    #[clap(long)]
+    creds: Option<String>,
+
+    /// Only go over pages changed since the timestamp kept in this file, which is updated after the run.
+    /// `--prefix` and `--namespace` then filter the changes
+    #[clap(long)]
+    recent: Option<String>,
+
+    /// With `--recent`, start from this timestamp instead of the saved one
+    #[clap(long)]
+    since: Option<String>,
+
+    /// With `--recent`, only changes in this namespace
+    #[clap(long)]
+    namespace: Option<i64>,
+
+    /// For `--mode daemon`, poll once and exit
+    #[clap(long)]
+    once: bool,
+
+    /// With `--apply`, leave pages alone if a human edited them this many minutes ago or less
+    #[clap(long, default_value = "30")]
+    human_window_mins: i64,
+
+    /// For `--mode move`, the new title of `--page`
+    #[clap(long)]
+    to: Option<String>,
+
+    /// For `--mode move`, don't leave a redirect behind
+    #[clap(long)]
+    no_redirect: bool,
+
+    /// For `--mode move`, also move the talk page
+    #[clap(long)]
+    move_talk: bool,
+
+    /// For `--mode move`, also move the subpages
+    #[clap(long)]
+    move_subpages: bool,
+
+    /// For `--mode links`, also point links that go through redirects at the pages they end up at
+    #[clap(long)]
+    fix: bool,
 }
+
 pub async fn stuff() {
     let args = Args::parse();
-    let mut api = mediawiki::api::Api::new(WIKI_URL).await.unwrap();
-    api.set_user_agent("dustloop botto (by moxian)");
-    api.set_edit_delay(Some(100));
-    
-    let token = &get_edit_token(&mut api).await;
+    let profile = profile::Profile::load(&args.profiles, &args.profile).unwrap();
+    if (args.apply || args.publish.is_some()) && !profile.writable {
+        panic!(
+            "profile {:?} in {} is not writable",
+            args.profile, args.profiles
+        );
+    }
+    let mut api = profile.connect().await.unwrap();
+    let editing = args.apply || args.publish.is_some();
+    let session = &mut profile
+        .session(&mut api, args.creds.as_deref(), editing)
+        .await
+        .unwrap();
+    let mut runner = Runner {
+        policy: WarningPolicy::load(&args.warning_policy).unwrap(),
+        verify: args.verify,
+        semantic_diff: args.semantic_diff,
+        diagnostics: vec![],
+    };
 
     // all_pages = vec!["User:Moxian/Sandbox".into()];
 
-    match args.mode.as_str() {
-        "color" => {
-            let mut all_pages = api::all_pages_with_prefix(&api, "GGACR/").await;
-            let skip_config: SkipConfig =
-                json5::from_str(&std::fs::read_to_string("data/skip_pages.json5").unwrap())
-                    .unwrap();
-            let skip_pages = skip_config.skip_pages.as_slice();
-
-            all_pages = all_pages
-                .into_iter()
-                // .filter(|p| p.as_str() >= "GGACR/Offense")
-                .filter(|p| !skip_pages.contains(&p))
-                .collect();
-
-            for title in all_pages.iter() {
-                println!("{}", title);
-                let (page_meta, content) = api::get_existing_page_text(&api, title).await.unwrap();
-                let existing_text = content.as_str();
-                let new_text = colorize_page(title, existing_text);
-                if true {
-                    continue;
-                }
-                let new_text = new_text.unwrap();
-                // println!("{}", new_text)
-                // return
-                if args.apply && false {
-                    println!("Editing..  {} ", title);
-                    api::edit_page(
-                        &api,
-                        token,
-                        &page_meta,
-                        &new_text,
-                        "Switch clr usage from numbers to letters",
-                        false,
-                    )
-                    .await
-                    .unwrap();
-                    // return;
-                }
-            }
+    let (pages, recent_state) = match &args.recent {
+        Some(state_path) => {
+            let filter = recent::Filter {
+                prefix: args.prefix.as_deref(),
+                namespace: args.namespace,
+            };
+            let (pages, state) = recent::pages(&api, state_path, args.since.as_deref(), &filter)
+                .await
+                .unwrap();
+            (Some(pages), Some(state))
         }
-        "combo" => {
-            let page = "GGST/Jack-O/Combos";
-            let (_page_meta, content) = api::get_existing_page_text(&api, page).await.unwrap();
-            let new_text = templatize_combo(&content).unwrap();
-            dump_file("combo", page, &new_text);
-            // println!("{}", new_text);
+        None => (pages_from_args(&args, &api).await, None),
+    };
+
+    if let Some(entry) = transform::find(&args.mode) {
+        let prepared = entry.prepare(args.config.as_deref()).unwrap();
+        run_transform(
+            prepared.as_ref(),
+            pages,
+            &args,
+            &mut api,
+            session,
+            &mut runner,
+            lint::Report::default(),
+        )
+        .await;
+    } else {
+        run_other_mode(&args, pages, &mut api, session, &mut runner).await;
+    }
+
+    if let (Some(path), Some(state)) = (&args.recent, recent_state) {
+        state.save(path).unwrap();
+    }
+
+    if let Some(path) = &args.diagnostics_json {
+        diagnostics::write_json(path, &runner.diagnostics).unwrap();
+    }
+}
+
+/// The shared page loop of every `transform::Transform`. `report` has whatever
+/// the mode found before it, to be published with the rest.
+async fn run_transform(
+    prepared: &dyn transform::Prepared,
+    pages: Option<Vec<String>>,
+    args: &Args,
+    api: &mut mediawiki::api::Api,
+    session: &mut api::Session,
+    runner: &mut Runner,
+    mut report: lint::Report,
+) {
+    let job = prepared.job();
+    let pages = match (pages, &job.pages) {
+        // recently changed pages are only the ones the config is about
+        (Some(mut pages), Some(spec)) if args.recent.is_some() => {
+            pages.retain(|p| spec.matches(p).unwrap());
+            pages
+        }
+        (Some(pages), Some(spec)) => {
+            let outside = pages
+                .iter()
+                .filter(|p| !spec.matches(p).unwrap())
+                .collect::<Vec<_>>();
+            if !outside.is_empty() {
+                eprintln!(
+                    "warning: {:?} aren't among the pages {} is configured for, going over them anyway",
+                    outside,
+                    prepared.name()
+                );
+            }
+            pages
         }
-        "movecard" => {
-            let page = args.page.as_deref().unwrap();
-            let (_page_meta, content) = api::get_existing_page_text(&api, page).await.unwrap();
-            let mut visitor = visitors::movecard::MoveCardVisitor::new();
-            let new_text = parse::transform_text(&content, &mut visitor, false).unwrap();
-            dump_file(&args.mode, page, &new_text);
+        (Some(pages), None) => pages,
+        (None, Some(spec)) => spec.resolve(api).await.unwrap(),
+        (None, None) => panic!("specify --page, --prefix or --recent"),
+    };
+    if args.apply {
+        if let Some(reason) = &job.refuse_apply {
+            panic!("{} refuses to --apply: {}", prepared.name(), reason);
         }
-        "findnreplace" => {
-            #[derive(serde::Deserialize)]
-            #[serde(untagged)]
-            enum PagesSpec {
-                List(Vec<String>),
-                Spec { prefix: String, pattern: String },
+    }
+    println!("pages list: {:?}", pages);
+    let window = time::Duration::minutes(args.human_window_mins);
+    // pages humans were editing get one more go at the end
+    let mut queue: std::collections::VecDeque<(String, bool)> =
+        pages.into_iter().map(|p| (p, false)).collect();
+    let mut skipped = vec![];
+    let mut mismatched = vec![];
+    while let Some((title, deferred)) = queue.pop_front() {
+        let title = &title;
+        println!("{}", title);
+        let (page_meta, content) = match api::get_existing_page_text(api, title).await.unwrap() {
+            Some(p) => p,
+            None => {
+                println!(".. missing!");
+                continue;
+            }
+        };
+        if args.apply {
+            let editor = api::recent_human_editor(api, &page_meta, window)
+                .await
+                .unwrap();
+            if let Some(editor) = editor {
+                println!(".. edited by {} at {}", editor, page_meta.last_edited);
+                if deferred {
+                    skipped.push(title.clone());
+                } else {
+                    println!(".. trying again at the end");
+                    queue.push_back((title.clone(), true));
+                }
+                continue;
             }
-            #[derive(serde::Deserialize)]
-            struct Config {
-                pages: PagesSpec,
-                changes: visitors::FindReplaceConfig,
-                #[serde(default)]
-                apply: bool,
-                #[serde(default)]
-                comment: String,
-                isminor: Option<bool>,
+        }
+        report.pages_checked += 1;
+        let first_new = runner.diagnostics.len();
+        let new_text = prepared.transform(title, &content, runner);
+        report.findings.extend(
+            runner.diagnostics[first_new..]
+                .iter()
+                .map(|d| lint::Finding {
+                    lint: prepared.name(),
+                    diagnostic: d.clone(),
+                }),
+        );
+        let new_text = match new_text {
+            Ok(t) => t,
+            Err(e) => {
+                println!(".. failed: {}", e);
+                continue;
             }
-            let config: Config = json5::from_str(
-                &std::fs::read_to_string(args.config.as_ref().context("specify --config").unwrap())
-                    .unwrap(),
+        };
+        dump_file(prepared.name(), title, &new_text);
+        if new_text == content {
+            println!(".. no changes!");
+            continue;
+        }
+        println!(".. done");
+        report.findings.extend(lint::dry_run_findings(
+            prepared.name(),
+            title,
+            &content,
+            &new_text,
+        ));
+
+        if args.apply {
+            println!("Editing..  {} ", title);
+            let edited = api::edit_page_changes(
+                api,
+                session,
+                &page_meta,
+                &content,
+                &new_text,
+                &prepared.summary(),
+                job.minor,
             )
-            .map_err(|e| {
-                println!("{}", e);
-            })
+            .await
             .unwrap();
-            let pages = match config.pages {
-                PagesSpec::List(p) => p,
-                PagesSpec::Spec { prefix, pattern } => {
-                    let re = regex::Regex::new(&pattern).unwrap();
-                    api::all_pages_with_prefix(&api, &prefix)
-                        .await
-                        .into_iter()
-                        .filter(|p| re.is_match(p))
-                        .collect::<Vec<_>>()
+            match edited {
+                api::Edited::Saved(revid) => println!(".. saved as {:?}", revid),
+                api::Edited::NoChange => println!(".. the wiki already had that"),
+                api::Edited::Mismatch { revid, line } => {
+                    println!(
+                        ".. saved, but {:?} differs from what was sent at line {}",
+                        revid, line
+                    );
+                    mismatched.push(title.clone());
                 }
-            };
-            println!("pages list: {:?}", pages);
+            }
+        }
+    }
+    if !skipped.is_empty() {
+        println!("skipped while humans were editing: {:?}", skipped);
+    }
+    if !mismatched.is_empty() {
+        println!("changed by the wiki on save, have a look: {:?}", mismatched);
+    }
+    publish_report(args, api, session, &report).await;
+}
+
+const REPORT_SUMMARY: &str = "Update maintenance report";
+
+/// Saves the report to `--publish`, if given, unless it's already there.
+async fn publish_report(
+    args: &Args,
+    api: &mut mediawiki::api::Api,
+    session: &mut api::Session,
+    report: &lint::Report,
+) {
+    let page = match &args.publish {
+        Some(p) => p,
+        None => return,
+    };
+    let edited = api::save_page(
+        api,
+        session,
+        page,
+        &report.render_wikitext(),
+        REPORT_SUMMARY,
+    )
+    .await
+    .unwrap();
+    match edited {
+        api::Edited::NoChange => println!("report on {} is up to date", page),
+        api::Edited::Saved(_) => println!("published the report to {}", page),
+        api::Edited::Mismatch { line, .. } => println!(
+            "published the report to {}, but the wiki changed it at line {}",
+            page, line
+        ),
+    }
+}
+
+const MOVE_REASON: &str = "Rename";
+
+/// What `--mode move` would move without `--apply`. Talk pages aren't known
+/// until the wiki does it.
+async fn planned_moves(
+    api: &mediawiki::api::Api,
+    from: &str,
+    to: &str,
+    options: &api::MoveOptions,
+) -> Vec<(String, String)> {
+    let mut moves = vec![(from.to_string(), to.to_string())];
+    if options.subpages {
+        let prefix = format!("{}/", from);
+        for sub in api::all_pages_with_prefix(api, &prefix).await {
+            let new = format!("{}/{}", to, &sub[prefix.len()..]);
+            moves.push((sub, new));
+        }
+    }
+    moves
+}
+
+/// `--page` or `--prefix`, if given.
+async fn pages_from_args(args: &Args, api: &mediawiki::api::Api) -> Option<Vec<String>> {
+    match (&args.page, &args.prefix) {
+        (Some(page), _) => Some(vec![page.clone()]),
+        (None, Some(prefix)) => Some(api::all_pages_with_prefix(api, prefix).await),
+        (None, None) => None,
+    }
+}
+
+/// Modes that aren't transforms.
+async fn run_other_mode(
+    args: &Args,
+    pages: Option<Vec<String>>,
+    api: &mut mediawiki::api::Api,
+    session: &mut api::Session,
+    runner: &mut Runner,
+) {
+    match args.mode.as_str() {
+        "daemon" => {
+            let config = args.config.as_deref().context("specify --config").unwrap();
+            let mut daemon = daemon::Daemon::load(config, args.apply).unwrap();
+            daemon.run(api, session, runner, args.once).await.unwrap();
+        }
+        "query" => {
+            let selector = selector::Selector::parse(
+                args.query.as_deref().context("specify --query").unwrap(),
+            )
+            .unwrap();
+            let pages = pages.expect("specify --page, --prefix or --recent");
+            let mut total = 0;
             for page in &pages {
-                let (page_meta, content) = api::get_existing_page_text(&api, page).await.unwrap();
-                println!("Page: {}", page);
-                let new_text = find_n_replace(&content, &config.changes).unwrap();
-                let file = std::path::Path::new("out/find_n_repalce").join(page.replace("/", "_"));
-                std::fs::create_dir_all(file.parent().unwrap()).unwrap();
-                let mut f = std::fs::File::create(file).unwrap();
-                f.write_all(new_text.as_bytes()).unwrap();
-
-                if new_text != content {
-                    println!(".. done");
-                } else {
-                    println!(".. no changes!");
-                    continue;
+                let (_, content) = match api::get_existing_page_text(api, page).await.unwrap() {
+                    Some(p) => p,
+                    None => {
+                        println!("{}: missing", page);
+                        continue;
+                    }
+                };
+                let matches = query_page(page, &content, &selector);
+                total += matches.len();
+                for m in matches {
+                    println!("{}", m);
                 }
-
-                if args.apply {
-                    if !config.apply {
-                        panic!("noaply in config")
+            }
+            println!("{} match(es) in {} page(s)", total, pages.len());
+        }
+        "lint" => {
+            let lints = lint::select(args.lints.as_deref()).unwrap();
+            let pages = pages.expect("specify --page, --prefix or --recent");
+            let mut report = lint::Report::default();
+            for title in &pages {
+                let (_, content) = match api::get_existing_page_text(api, title).await.unwrap() {
+                    Some(p) => p,
+                    None => {
+                        eprintln!("{}: missing", title);
+                        continue;
                     }
-                    if config.comment.is_empty() {
-                        panic!("no comment in config")
+                };
+                report.pages_checked += 1;
+                let findings = lint::lint_page(&lints, title, &content, &runner.policy);
+                eprintln!("{}: {} finding(s)", title, findings.len());
+                runner
+                    .diagnostics
+                    .extend(findings.iter().map(|f| f.diagnostic.clone()));
+                report.findings.extend(findings);
+            }
+            let rendered = report.render(&args.lint_format).unwrap();
+            match &args.lint_output {
+                Some(path) => std::fs::write(path, rendered).unwrap(),
+                None => print!("{}", rendered),
+            }
+            publish_report(args, api, session, &report).await;
+        }
+        "move" => {
+            let from = args.page.as_deref().context("specify --page").unwrap();
+            let to = args.to.as_deref().context("specify --to").unwrap();
+            let options = api::MoveOptions {
+                redirect: !args.no_redirect,
+                talk: args.move_talk,
+                subpages: args.move_subpages,
+            };
+            let moves = if args.apply {
+                api::move_page(api, session, from, to, MOVE_REASON, &options)
+                    .await
+                    .unwrap()
+            } else {
+                planned_moves(api, from, to, &options).await
+            };
+            let mut pages = vec![];
+            for (old, new) in &moves {
+                let verb = if args.apply { "moved" } else { "would move" };
+                println!("{} {} to {}", verb, old, new);
+                for page in api::backlinks(api, old, false).await.unwrap() {
+                    if !pages.contains(&page) {
+                        pages.push(page);
                     }
-                    let isminor = config.isminor.unwrap();
-                    api::edit_page(&api, token, &page_meta, &new_text, &config.comment, isminor)
-                        .await
-                        .unwrap();
                 }
+                let redirects = api::backlinks(api, old, true).await.unwrap();
+                if !redirects.is_empty() {
+                    println!("redirects to {} left as they are: {:?}", old, redirects);
+                }
+            }
+            let relink = visitors::RelinkTransform::new(moves).for_backlinks();
+            let report = lint::Report::default();
+            run_transform(&relink, Some(pages), args, api, session, runner, report).await;
+        }
+        "links" => {
+            let pages = pages.expect("specify --page, --prefix or --recent");
+            let mut texts = vec![];
+            for title in pages {
+                match api::get_existing_page_text(api, &title).await.unwrap() {
+                    Some((_, text)) => {
+                        let links = links::page_links(&title, &text);
+                        texts.push((title, text, links));
+                    }
+                    None => eprintln!("{}: missing", title),
+                }
+            }
+            let all_links = texts.iter().flat_map(|t| &t.2).collect::<Vec<_>>();
+            let index = links::LinkIndex::load(api, &all_links).await.unwrap();
+            let mut report = lint::Report::default();
+            for (title, text, links) in &texts {
+                let findings = index.check(title, text, links);
+                eprintln!("{}: {} finding(s)", title, findings.len());
+                runner
+                    .diagnostics
+                    .extend(findings.iter().map(|f| f.diagnostic.clone()));
+                report.findings.extend(findings);
+            }
+            report.pages_checked = texts.len();
+            let rendered = report.render(&args.lint_format).unwrap();
+            match &args.lint_output {
+                Some(path) => std::fs::write(path, rendered).unwrap(),
+                None => print!("{}", rendered),
+            }
+            if !args.fix {
+                publish_report(args, api, session, &report).await;
+                return;
             }
-            // println!("{}", new_text);
+            // the loop counts them again
+            report.pages_checked = 0;
+            let relink = visitors::RelinkTransform::new(index.redirects());
+            let pages = texts.into_iter().map(|t| t.0).collect();
+            run_transform(&relink, Some(pages), args, api, session, runner, report).await;
         }
-        "stuff" => {
-            let mut all_pages = api::all_pages_with_prefix(&api, "GGST").await;
-            all_pages = all_pages
-                .into_iter()
-                // .filter(|p| p.as_str() >= "GGACR/Offense")
-                // .filter(|p| !skip_pages.contains(&p))
-                .collect();
-            let url_re = regex::Regex::new(r"https?://(www\.)?dustloop.com/\S*").unwrap();
-            for title in &all_pages {
-                println!("{}", title);
-                let (_, content) = api::get_existing_page_text(&api, title).await.unwrap();
-                let matches = url_re
-                    .find_iter(&content)
-                    .map(|m| m.as_str())
-                    .collect::<Vec<_>>();
-                if !matches.is_empty() {
-                    println!("found {:?} matches: {:?}", matches.len(), matches);
+        "redirects" => {
+            let prefix = args.prefix.as_deref().unwrap_or("");
+            let redirects = redirects::Redirects::load(api, prefix).await.unwrap();
+            let mut report = lint::Report::default();
+            let mut targets = std::collections::BTreeMap::new();
+            for (title, text) in &redirects.pages {
+                let resolution = redirects.resolve(title);
+                if let redirects::Resolution::Double { target, .. } = &resolution {
+                    targets.insert(title.clone(), target.clone());
                 }
+                report
+                    .findings
+                    .extend(redirects::finding(title, text, &resolution));
             }
+            let diagnostics = report.findings.iter().map(|f| f.diagnostic.clone());
+            runner.diagnostics.extend(diagnostics);
+            diagnostics::print_diagnostics(&runner.diagnostics);
+            println!(
+                "{} redirect(s), {} to fix, {} finding(s)",
+                redirects.pages.len(),
+                targets.len(),
+                report.findings.len()
+            );
+            // the loop counts the ones it fixes
+            report.pages_checked = redirects.pages.len() - targets.len();
+            let pages = targets.keys().cloned().collect();
+            let retarget = visitors::RetargetTransform::new(targets);
+            run_transform(&retarget, Some(pages), args, api, session, runner, report).await;
         }
-        _ => panic!(),
+        mode => panic!("unknown mode {:?}", mode),
     }
 }
@@ -0,0 +1,189 @@
+//! `--mode links`: checks that every `[[Target#Anchor|text]]` on the pages goes
+//! to a page that exists and has that anchor, and finds the ones that only get
+//! there through a redirect.
+
+use crate::api::{self, Landing};
+use crate::diagnostics::{Diagnostic, LineIndex, Severity};
+use crate::lint::Finding;
+use crate::parse;
+use crate::selector::{Selector, Tree};
+use crate::visitors::normalize_title;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// An internal link on a page.
+pub struct Link {
+    /// Normalized, with `[[#Anchor]]` pointing at the page itself.
+    pub title: String,
+    pub anchor: Option<String>,
+    pub span: std::ops::Range<usize>,
+}
+
+/// Every internal link of `page` whose target is spelled out, not built by
+/// templates or parser functions.
+pub fn page_links(page: &str, text: &str) -> Vec<Link> {
+    let parsed = parse::parse_text(text);
+    let tree = Tree::new(&parsed.nodes, text);
+    let selector = Selector::parse("link").unwrap();
+    let mut links = vec![];
+    for element in tree.select(&selector) {
+        let target = element.attribute("target").unwrap_or("").trim();
+        if target.contains(['{', '}', '<']) {
+            continue;
+        }
+        let target = target.trim_start_matches(':');
+        let (title, anchor) = match target.split_once('#') {
+            Some((title, anchor)) => (title, Some(anchor.trim().to_string())),
+            None => (target, None),
+        };
+        let title = match title.trim() {
+            "" => normalize_title(page),
+            title => normalize_title(title),
+        };
+        links.push(Link {
+            title,
+            anchor: anchor.filter(|a| !a.is_empty()),
+            span: element.range.clone(),
+        });
+    }
+    links
+}
+
+/// Anchors compare with `_` and spaces the same.
+fn normalize_anchor(anchor: &str) -> String {
+    anchor
+        .replace('_', " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// What a link can point at on a page: heading titles, as shown, `{{anchor}}`
+/// parameters and `id=` attributes.
+pub fn anchors(text: &str) -> BTreeSet<String> {
+    static LINK: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    static ID: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let link = LINK.get_or_init(|| regex::Regex::new(r"\[\[(?:[^|\]]*\|)?([^\]]*)\]\]").unwrap());
+    let id = ID.get_or_init(|| regex::Regex::new(r#"\bid\s*=\s*["']?([^"'>|]+)"#).unwrap());
+
+    let parsed = parse::parse_text(text);
+    let tree = Tree::new(&parsed.nodes, text);
+    let selector = Selector::parse(r#"heading, template[name~="(?i)^anchors?$"] > param"#).unwrap();
+    let mut out = BTreeSet::new();
+    for element in tree.select(&selector) {
+        let anchor = match element.kind {
+            "heading" => {
+                let title = element.attribute("title").unwrap_or("");
+                link.replace_all(title, "$1")
+                    .replace("'''", "")
+                    .replace("''", "")
+            }
+            _ => element.attribute("value").unwrap_or("").to_string(),
+        };
+        out.insert(normalize_anchor(&anchor));
+    }
+    for cap in id.captures_iter(text) {
+        out.insert(normalize_anchor(&cap[1]));
+    }
+    out
+}
+
+/// Where the links of a run go, and the anchors of the pages they go to.
+pub struct LinkIndex {
+    landings: BTreeMap<String, Landing>,
+    /// Only for pages some link wants an anchor of.
+    anchors: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl LinkIndex {
+    pub async fn load(api: &mediawiki::api::Api, links: &[&Link]) -> anyhow::Result<Self> {
+        let titles = links
+            .iter()
+            .map(|l| l.title.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let landings = api::link_landings(api, &titles).await?;
+        let wanted = links
+            .iter()
+            .filter_map(|l| {
+                let landing = landings.get(&l.title)?;
+                let wants = landing.exists && (l.anchor.is_some() || landing.fragment.is_some());
+                wants.then(|| landing.title.clone())
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let anchors = api::page_texts(api, &wanted)
+            .await?
+            .into_iter()
+            .map(|(title, text)| (title, anchors(&text)))
+            .collect();
+        Ok(Self { landings, anchors })
+    }
+
+    /// Titles that redirect, and where to, `#fragment` included.
+    pub fn redirects(&self) -> Vec<(String, String)> {
+        self.landings
+            .iter()
+            .filter(|(_, landing)| landing.redirected && landing.exists)
+            .map(|(title, landing)| {
+                let target = match &landing.fragment {
+                    Some(fragment) => format!("{}#{}", landing.title, fragment),
+                    None => landing.title.clone(),
+                };
+                (title.clone(), target)
+            })
+            .collect()
+    }
+
+    /// What's wrong with the links of one page.
+    pub fn check(&self, page: &str, text: &str, links: &[Link]) -> Vec<Finding> {
+        let index = LineIndex::new(text);
+        let mut findings = vec![];
+        for link in links {
+            let landing = match self.landings.get(&link.title) {
+                Some(landing) => landing,
+                None => continue,
+            };
+            let (severity, code, message) = if !landing.exists {
+                (
+                    Severity::Error,
+                    "link/missing",
+                    format!("link to missing page {}", link.title),
+                )
+            } else if let Some(anchor) = self.missing_anchor(link, landing) {
+                (
+                    Severity::Warning,
+                    "link/anchor",
+                    format!("no section or anchor {:?} on {}", anchor, landing.title),
+                )
+            } else if landing.redirected {
+                (
+                    Severity::Info,
+                    "link/redirect",
+                    format!("{} redirects to {}", link.title, landing.title),
+                )
+            } else {
+                continue;
+            };
+            let mut diagnostic = Diagnostic::new(severity, code, link.span.clone(), message);
+            diagnostic.locate(page, &index, text);
+            findings.push(Finding {
+                lint: "links",
+                diagnostic,
+            });
+        }
+        findings
+    }
+
+    /// The anchor the link ends up at, if the page doesn't have it. The link's
+    /// own anchor wins over the redirect's.
+    fn missing_anchor<'l>(&'l self, link: &'l Link, landing: &'l Landing) -> Option<&'l str> {
+        let anchor = link.anchor.as_ref().or(landing.fragment.as_ref())?;
+        let known = self.anchors.get(&landing.title)?;
+        match known.contains(&normalize_anchor(anchor)) {
+            true => None,
+            false => Some(anchor),
+        }
+    }
+}
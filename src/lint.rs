@@ -0,0 +1,309 @@
+//! `--mode lint`: checks that never edit, only report.
+//!
+//! Every page is parsed once and each lint walks the result with its own
+//! visitor. Whatever diagnostics come out are kept as findings, replacements are
+//! thrown away. Findings are grouped by lint and page, and can be rendered as
+//! text, JSON or a wikitext table.
+
+use crate::diagnostics::{Diagnostic, LineIndex, Severity};
+use crate::parse::{self, WikiVisitor};
+use crate::prelude::*;
+use crate::semantic_diff;
+use crate::transform::Transform;
+use crate::visitors;
+use crate::warning_policy::WarningPolicy;
+use anyhow::Context;
+use parse_wiki_text::Node;
+use std::collections::BTreeMap;
+
+/// One check run over every page.
+pub trait Lint {
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    fn applies_to(&self, _title: &str) -> bool {
+        true
+    }
+    fn check(&self, title: &str, text: &str, nodes: &[Node]) -> anyhow::Result<Vec<Diagnostic>>;
+}
+
+/// A lint made of a transform's visitor, keeping its diagnostics only.
+struct TransformLint<T> {
+    name: &'static str,
+    description: &'static str,
+    applies_to: fn(&T, &str) -> bool,
+    transform: T,
+}
+
+impl<T: Transform> Lint for TransformLint<T> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    fn description(&self) -> &'static str {
+        self.description
+    }
+    fn applies_to(&self, title: &str) -> bool {
+        (self.applies_to)(&self.transform, title)
+    }
+    fn check(&self, title: &str, text: &str, nodes: &[Node]) -> anyhow::Result<Vec<Diagnostic>> {
+        let mut visitor = self.transform.visitor(title, text)?;
+        parse::walk(&mut visitor, nodes, text);
+        Ok(visitor.take_diagnostics())
+    }
+}
+
+/// Links to dustloop spelled out as full URLs rather than wikilinks.
+struct AbsoluteUrls {
+    re: regex::Regex,
+}
+
+struct AbsoluteUrlVisitor<'a> {
+    re: &'a regex::Regex,
+    base_text: &'a str,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl WikiVisitor for AbsoluteUrlVisitor<'_> {
+    fn take_replacements(&mut self) -> Vec<(String, std::ops::Range<usize>)> {
+        vec![]
+    }
+    fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+    fn visit_text(&mut self, node: &Node) {
+        for m in self.re.find_iter(node.as_str(self.base_text)) {
+            let span = (node.start() + m.start())..(node.start() + m.end());
+            self.diagnostics.push(Diagnostic::new(
+                Severity::Warning,
+                "lint/absolute-url",
+                span,
+                format!("absolute dustloop URL {}", m.as_str()),
+            ));
+        }
+    }
+}
+
+impl Lint for AbsoluteUrls {
+    fn name(&self) -> &'static str {
+        "absolute-urls"
+    }
+    fn description(&self) -> &'static str {
+        "dustloop pages linked by URL instead of by title"
+    }
+    fn check(&self, _title: &str, text: &str, nodes: &[Node]) -> anyhow::Result<Vec<Diagnostic>> {
+        let mut visitor = AbsoluteUrlVisitor {
+            re: &self.re,
+            base_text: text,
+            diagnostics: vec![],
+        };
+        parse::walk(&mut visitor, nodes, text);
+        Ok(visitor.take_diagnostics())
+    }
+}
+
+/// Every lint there is, loaded.
+pub fn registry() -> anyhow::Result<Vec<Box<dyn Lint>>> {
+    Ok(vec![
+        Box::new(TransformLint {
+            name: "clr-usage",
+            description: "clr templates with unknown colors, bad arguments or unknown moves",
+            applies_to: visitors::ColorTransform::handles,
+            transform: visitors::ColorTransform::load(None)?,
+        }),
+        Box::new(TransformLint {
+            name: "combo-columns",
+            description: "combo tables whose rows don't match their columns",
+            applies_to: |_, _| true,
+            transform: visitors::ComboTableTransform::load(None)?,
+        }),
+        Box::new(AbsoluteUrls {
+            re: regex::Regex::new(r"https?://(www\.)?dustloop\.com/\S*").unwrap(),
+        }),
+    ])
+}
+
+/// The lints named in `names` (comma separated), or all of them.
+pub fn select(names: Option<&str>) -> anyhow::Result<Vec<Box<dyn Lint>>> {
+    let mut all = registry()?;
+    let names = match names {
+        None => return Ok(all),
+        Some(n) => n.split(',').map(str::trim).collect::<Vec<_>>(),
+    };
+    for name in &names {
+        if !all.iter().any(|l| l.name() == *name) {
+            let known = all
+                .iter()
+                .map(|l| format!("\n  {}: {}", l.name(), l.description()))
+                .collect::<String>();
+            anyhow::bail!("unknown lint {:?}, known ones are:{}", name, known);
+        }
+    }
+    all.retain(|l| names.contains(&l.name()));
+    Ok(all)
+}
+
+/// A diagnostic, and the lint (or, in dry-run reports, the transform) it came from.
+#[derive(serde::Serialize, Clone)]
+pub struct Finding {
+    pub lint: &'static str,
+    #[serde(flatten)]
+    pub diagnostic: Diagnostic,
+}
+
+/// Parser warnings the policy doesn't ignore are reported as the `parse` lint.
+/// A lint that can't run on a page is reported as a `lint/failed` error.
+pub fn lint_page(
+    lints: &[Box<dyn Lint>],
+    title: &str,
+    text: &str,
+    policy: &WarningPolicy,
+) -> Vec<Finding> {
+    let parsed = parse::parse_text(text);
+    let index = LineIndex::new(text);
+    let mut findings = vec![];
+    let mut push = |lint, mut diagnostic: Diagnostic| {
+        diagnostic.locate(title, &index, text);
+        findings.push(Finding { lint, diagnostic });
+    };
+    for warning in policy.apply(title, text, &parsed.warnings) {
+        push("parse", Diagnostic::from(&warning));
+    }
+    for lint in lints.iter().filter(|l| l.applies_to(title)) {
+        match lint.check(title, text, &parsed.nodes) {
+            Ok(diagnostics) => diagnostics.into_iter().for_each(|d| push(lint.name(), d)),
+            Err(e) => push(
+                lint.name(),
+                Diagnostic::new(Severity::Error, "lint/failed", 0..0, format!("{:#}", e)),
+            ),
+        }
+    }
+    findings
+}
+
+/// What a transform would do to a page, one `diff/<node kind>` info per
+/// semantic change, for dry-run reports.
+pub fn dry_run_findings(name: &'static str, title: &str, old: &str, new: &str) -> Vec<Finding> {
+    let index = LineIndex::new(old);
+    semantic_diff::diff(old, new)
+        .into_iter()
+        .map(|change| {
+            let span = change.old_range().unwrap_or(0..0);
+            let code = format!("diff/{}", change.node_kind());
+            let mut diagnostic = Diagnostic::new(Severity::Info, &code, span, change.render());
+            diagnostic.locate(title, &index, old);
+            Finding {
+                lint: name,
+                diagnostic,
+            }
+        })
+        .collect()
+}
+
+/// Findings of a whole run.
+#[derive(Default)]
+pub struct Report {
+    pub pages_checked: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// lint -> page -> findings, everything sorted.
+    fn grouped(&self) -> BTreeMap<&str, BTreeMap<&str, Vec<&Diagnostic>>> {
+        let mut out: BTreeMap<&str, BTreeMap<&str, Vec<&Diagnostic>>> = BTreeMap::new();
+        for f in &self.findings {
+            out.entry(f.lint)
+                .or_default()
+                .entry(f.diagnostic.page.as_str())
+                .or_default()
+                .push(&f.diagnostic);
+        }
+        for pages in out.values_mut() {
+            for diagnostics in pages.values_mut() {
+                diagnostics.sort_by_key(|d| (d.line, d.column));
+            }
+        }
+        out
+    }
+
+    pub fn render(&self, format: &str) -> anyhow::Result<String> {
+        match format {
+            "text" => Ok(self.render_text()),
+            "json" => self.render_json(),
+            "wikitext" => Ok(self.render_wikitext()),
+            _ => anyhow::bail!(
+                "unknown lint format {:?}, use text, json or wikitext",
+                format
+            ),
+        }
+    }
+
+    pub fn render_text(&self) -> String {
+        let mut out = String::new();
+        for (lint, pages) in self.grouped() {
+            let count: usize = pages.values().map(Vec::len).sum();
+            out += &format!(
+                "== {}: {} finding(s) on {} page(s)\n",
+                lint,
+                count,
+                pages.len()
+            );
+            for d in pages.values().flatten() {
+                out += &d.render();
+                out += "\n";
+            }
+        }
+        out += &format!(
+            "{} finding(s) in {} page(s) checked\n",
+            self.findings.len(),
+            self.pages_checked
+        );
+        out
+    }
+
+    pub fn render_json(&self) -> anyhow::Result<String> {
+        #[derive(serde::Serialize)]
+        struct Json<'a> {
+            pages_checked: usize,
+            lints: BTreeMap<&'a str, BTreeMap<&'a str, Vec<&'a Diagnostic>>>,
+        }
+        let json = Json {
+            pages_checked: self.pages_checked,
+            lints: self.grouped(),
+        };
+        serde_json::to_string_pretty(&json).context("serializing lint report")
+    }
+
+    /// A sortable wikitable, one row per finding.
+    pub fn render_wikitext(&self) -> String {
+        let mut out = format!(
+            "{} finding(s) in {} page(s) checked.\n\n",
+            self.findings.len(),
+            self.pages_checked
+        );
+        out += "{| class=\"wikitable sortable\"\n";
+        out += "! Lint !! Page !! Line !! Severity !! Code !! Message !! Snippet\n";
+        for (lint, pages) in self.grouped() {
+            for (page, diagnostics) in pages {
+                for d in diagnostics {
+                    out += &format!(
+                        // `:` so categories and files are linked, not added or shown
+                        "|-\n| {} || [[:{}]] || {} || {} || {} || {} || {}\n",
+                        lint,
+                        page,
+                        d.line,
+                        d.severity,
+                        d.code,
+                        nowiki(&d.message),
+                        nowiki(d.snippet.trim())
+                    );
+                }
+            }
+        }
+        out += "|}\n";
+        out
+    }
+}
+
+fn nowiki(text: &str) -> String {
+    let text = text.replace("</nowiki>", "&lt;/nowiki>").replace('\n', " ");
+    format!("<nowiki>{}</nowiki>", text)
+}
@@ -1,14 +1,34 @@
+use crate::diagnostics::{Diagnostic, LineIndex, Severity};
+use crate::selector::{Element, Selector, Tree};
+use crate::warning_policy::WarningPolicy;
 use parse_wiki_text::Node;
 
+/// Diagnostics from both the parser and the visitor end up in `diagnostics`,
+/// even when the page fails.
 pub fn transform_text(
+    title: &str,
     existing_text: &str,
     visitor: &mut impl WikiVisitor,
-    skip_errors: bool,
+    policy: &WarningPolicy,
+    diagnostics: &mut Vec<Diagnostic>,
 ) -> anyhow::Result<String> {
-    let parsed = parse_wiki_text::Configuration::new(&parse_wiki_text::ConfigurationSource {
+    let replacements = compute_replacements(title, existing_text, visitor, policy, diagnostics)?;
+    Ok(apply_replacements(existing_text, &replacements))
+}
+
+/// Building the configuration is far from free, so it's done once.
+static CONFIGURATION: std::sync::OnceLock<parse_wiki_text::Configuration> =
+    std::sync::OnceLock::new();
+
+pub fn parse_text(text: &str) -> parse_wiki_text::Output<'_> {
+    CONFIGURATION.get_or_init(configuration).parse(text)
+}
+
+fn configuration() -> parse_wiki_text::Configuration {
+    parse_wiki_text::Configuration::new(&parse_wiki_text::ConfigurationSource {
         link_trail: "/^([a-z]+)(.*)$/sD",
 
-        category_namespaces: &[],
+        category_namespaces: &["Category"],
         extension_tags: &[
             "big",
             "pre",
@@ -39,87 +59,153 @@ pub fn transform_text(
             "tabbertransclude",
             "seo",
         ],
-        file_namespaces: &[],
-        magic_words: &[],
-        protocols: &[],
-        redirect_magic_words: &[],
+        file_namespaces: &["File", "Image"],
+        magic_words: &[
+            "DISAMBIG",
+            "EXPECTUNUSEDCATEGORY",
+            "FORCETOC",
+            "HIDDENCAT",
+            "INDEX",
+            "NEWSECTIONLINK",
+            "NOCC",
+            "NOCONTENTCONVERT",
+            "NOEDITSECTION",
+            "NOGALLERY",
+            "NOINDEX",
+            "NONEWSECTIONLINK",
+            "NOTC",
+            "NOTITLECONVERT",
+            "NOTOC",
+            "STATICREDIRECT",
+            "TOC",
+        ],
+        protocols: &["//", "ftp://", "http://", "https://", "irc://", "mailto:"],
+        redirect_magic_words: &["REDIRECT"],
     })
-    .parse(existing_text);
-
-    let warnings = parsed
-        .warnings
-        .into_iter()
-        .filter(|w| {
-            ![
-                parse_wiki_text::WarningMessage::StrayTextInTable,
-                parse_wiki_text::WarningMessage::RepeatedEmptyLine,
-                parse_wiki_text::WarningMessage::InvalidLinkSyntax, // huh??
-                parse_wiki_text::WarningMessage::UnrecognizedTagName, // i'm tired
-            ]
-            .contains(&w.message)
-        })
-        .filter(|w| {
-            let text = String::from_utf8_lossy(
-                &existing_text.as_bytes()[w.start..w.end.min(existing_text.len())],
-            )
-            .to_string();
-            if w.message == parse_wiki_text::WarningMessage::UnrecognizedTagName {
-                if ["", "SlashGordon", "),"].contains(&text.as_str()) {
-                    return false;
-                }
-                if text.as_str().starts_with("=") {
-                    // <=
-                    return false;
-                }
-                if text.as_str().chars().next().unwrap().is_digit(10) {
-                    // <123frames or something
-                    return false;
-                }
-            }
-            return true;
-        })
-        .collect::<Vec<_>>();
+}
 
-    for w in &warnings {
-        let msg = &existing_text[w.start..w.end.min(existing_text.len())];
-        let snippet = &existing_text[(w.start.max(10) - 10)..(w.end + 10).min(existing_text.len())];
-        let msg = &msg[..msg.len().min(500)];
-        println!("{}: {}", w.message, msg);
-        println!(".. around: {}", snippet);
+/// Runs the visitor over the page, returning its replacements sorted and checked
+/// for overlaps.
+pub fn compute_replacements(
+    title: &str,
+    existing_text: &str,
+    visitor: &mut impl WikiVisitor,
+    policy: &WarningPolicy,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> anyhow::Result<Vec<(String, std::ops::Range<usize>)>> {
+    let parsed = parse_text(existing_text);
+
+    let line_index = LineIndex::new(existing_text);
+    let first_new = diagnostics.len();
+    let warnings = policy.apply(title, existing_text, &parsed.warnings);
+    diagnostics.extend(warnings.iter().map(Diagnostic::from));
+    for d in &mut diagnostics[first_new..] {
+        d.locate(title, &line_index, existing_text);
     }
-    if !skip_errors {
-        assert!(warnings.is_empty());
+    let failed = count_errors(&diagnostics[first_new..]);
+    if failed > 0 {
+        anyhow::bail!("{} parser warning(s) rejected by policy", failed);
     }
 
-    // let mut visitor = ColorVisitor::default();
-    visitor.set_base_text(existing_text);
-    visit_nodes(visitor, &parsed.nodes, existing_text);
+    walk(visitor, &parsed.nodes, existing_text);
 
-    let mut replacements = visitor.get_replacements()?.to_vec();
+    let first_new = diagnostics.len();
+    diagnostics.extend(visitor.take_diagnostics());
+    for d in &mut diagnostics[first_new..] {
+        d.locate(title, &line_index, existing_text);
+    }
+    let failed = count_errors(&diagnostics[first_new..]);
+    if failed > 0 {
+        anyhow::bail!("visitor reported {} error(s)", failed);
+    }
+
+    let mut replacements = visitor.take_replacements();
     replacements.sort_by_key(|(_, r)| r.start);
-    {
-        let mut last = 0;
-        for r in &replacements {
-            assert!(last < r.1.start);
-            last = r.1.end;
+    // visitors that don't agree fail the page, not the run
+    let overlaps = replacements
+        .windows(2)
+        .filter(|w| w[0].1.end > w[1].1.start)
+        .map(|w| {
+            let mut d = Diagnostic::new(
+                Severity::Error,
+                "visitor/overlap",
+                w[1].1.clone(),
+                format!(
+                    "replacement of {:?} overlaps the one of {:?}",
+                    w[1].1, w[0].1
+                ),
+            );
+            d.locate(title, &line_index, existing_text);
+            d
+        })
+        .collect::<Vec<_>>();
+    if !overlaps.is_empty() {
+        let count = overlaps.len();
+        diagnostics.extend(overlaps);
+        anyhow::bail!("{} overlapping replacement(s)", count);
+    }
+    Ok(replacements)
+}
+
+/// Takes the visitor over already parsed nodes: the node callbacks, then
+/// `visit_selected` for whatever its selector matches.
+pub fn walk(visitor: &mut impl WikiVisitor, nodes: &[Node], existing_text: &str) {
+    visitor.visit_page_start();
+    visit_nodes(visitor, nodes, existing_text);
+    if let Some(selector) = visitor.selector() {
+        let tree = Tree::new(nodes, existing_text);
+        let selected: Vec<&Element> = tree.select(selector);
+        for element in selected {
+            visitor.visit_selected(element);
         }
     }
+}
+
+/// `replacements` must be sorted and not overlap.
+pub fn apply_replacements(
+    existing_text: &str,
+    replacements: &[(String, std::ops::Range<usize>)],
+) -> String {
     let mut out = String::new();
     let mut last = 0;
-    for (rep, rang) in &replacements {
+    for (rep, rang) in replacements {
         out += &existing_text[last..rang.start];
         out += rep;
         last = rang.end;
     }
     out += &existing_text[last..];
+    out
+}
 
-    Ok(out)
+fn count_errors(diagnostics: &[Diagnostic]) -> usize {
+    diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count()
 }
 
+/// Visitors are made per page by their `transform::Transform`, borrowing the page
+/// text and whatever the transform prepared for the run.
 #[allow(unused_variables)]
 pub trait WikiVisitor {
-    fn set_base_text(&mut self, base_text: &str);
-    fn get_replacements(&self) -> anyhow::Result<&[(String, std::ops::Range<usize>)]>; // split into dedicated trait if needed
+    /// Called once, after the walk.
+    fn take_replacements(&mut self) -> Vec<(String, std::ops::Range<usize>)>;
+    fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        vec![]
+    }
+    /// Node kinds (as in `NodeExt::kind`) the visitor is meant to change.
+    /// `None` means anything goes; otherwise `--verify` flags changes to other kinds.
+    fn touches(&self) -> Option<&'static [&'static str]> {
+        None
+    }
+    /// Elements to hand to `visit_selected`, once the regular visit is done.
+    fn selector(&self) -> Option<&Selector> {
+        None
+    }
+    fn visit_selected(&mut self, element: &Element) {}
+
+    /// Before any node.
+    fn visit_page_start(&mut self) {}
 
     fn visit_template(&mut self, node: &Node) {}
     fn visit_table_start(&mut self, node: &Node) {}
@@ -127,7 +213,17 @@ pub trait WikiVisitor {
     fn visit_table_end(&mut self, node: &Node) {}
 
     fn visit_start_tag(&mut self, node: &Node) {}
+    /// Extension tags like `<nowiki>` and `<ref>`, before what's inside them.
+    fn visit_tag(&mut self, node: &Node) {}
     fn visit_heading(&mut self, node: &Node) {}
+
+    fn visit_link(&mut self, node: &Node) {}
+    fn visit_external_link(&mut self, node: &Node) {}
+    fn visit_image(&mut self, node: &Node) {}
+    fn visit_category(&mut self, node: &Node) {}
+    fn visit_redirect(&mut self, node: &Node) {}
+    fn visit_magic_word(&mut self, node: &Node) {}
+    fn visit_text(&mut self, node: &Node) {}
 }
 
 fn visit_node(visitor: &mut impl WikiVisitor, node: &parse_wiki_text::Node, existing_text: &str) {
@@ -136,9 +232,7 @@ fn visit_node(visitor: &mut impl WikiVisitor, node: &parse_wiki_text::Node, exis
             name, parameters, ..
         } => {
             visitor.visit_template(node);
-            for n in name {
-                visit_node(visitor, n, existing_text);
-            }
+            visit_nodes(visitor, name, existing_text);
             for param in parameters {
                 if let Some(ns) = &param.name {
                     visit_nodes(visitor, ns, existing_text);
@@ -150,18 +244,41 @@ fn visit_node(visitor: &mut impl WikiVisitor, node: &parse_wiki_text::Node, exis
             visitor.visit_heading(node);
             visit_nodes(visitor, nodes, existing_text);
         }
-        Node::Tag { nodes, .. }
-        | Node::Link { text: nodes, .. }
-        | Node::Preformatted { nodes, .. } => {
-            for n in nodes {
-                visit_node(visitor, &n, existing_text)
-            }
+        Node::Link { text: nodes, .. } => {
+            visitor.visit_link(node);
+            visit_nodes(visitor, nodes, existing_text);
+        }
+        Node::Tag { nodes, .. } => {
+            visitor.visit_tag(node);
+            visit_nodes(visitor, nodes, existing_text)
+        }
+        Node::Preformatted { nodes, .. } => visit_nodes(visitor, nodes, existing_text),
+        Node::ExternalLink { nodes, .. } => {
+            visitor.visit_external_link(node);
+            visit_nodes(visitor, nodes, existing_text);
+        }
+        Node::Image { text, .. } => {
+            visitor.visit_image(node);
+            // caption and the rest of the `|`-separated options
+            visit_nodes(visitor, text, existing_text);
+        }
+        Node::Category { ordinal, .. } => {
+            visitor.visit_category(node);
+            visit_nodes(visitor, ordinal, existing_text);
+        }
+        Node::Redirect { .. } => {
+            visitor.visit_redirect(node);
+        }
+        Node::MagicWord { .. } => {
+            visitor.visit_magic_word(node);
         }
         Node::StartTag { .. } => {
             visitor.visit_start_tag(node);
         }
-        Node::Text { .. }
-        | Node::EndTag { .. }
+        Node::Text { .. } => {
+            visitor.visit_text(node);
+        }
+        Node::EndTag { .. }
         | Node::Comment { .. }
         | Node::ParagraphBreak { .. }
         | Node::HorizontalDivider { .. }
@@ -176,42 +293,28 @@ fn visit_node(visitor: &mut impl WikiVisitor, node: &parse_wiki_text::Node, exis
             ..
         } => {
             visitor.visit_table_start(node);
-            for n in attributes {
-                visit_node(visitor, &n, existing_text)
-            }
+            visit_nodes(visitor, attributes, existing_text);
             for cap in captions {
                 if let Some(atts) = &cap.attributes {
-                    for n in atts {
-                        visit_node(visitor, &n, existing_text)
-                    }
-                }
-                for n in &cap.content {
-                    visit_node(visitor, &n, existing_text)
+                    visit_nodes(visitor, atts, existing_text);
                 }
+                visit_nodes(visitor, &cap.content, existing_text);
             }
             for row in rows {
                 visitor.visit_table_row(row);
-                for n in &row.attributes {
-                    visit_node(visitor, &n, existing_text)
-                }
+                visit_nodes(visitor, &row.attributes, existing_text);
                 for cell in &row.cells {
                     if let Some(atts) = &cell.attributes {
-                        for n in atts {
-                            visit_node(visitor, &n, existing_text)
-                        }
-                    }
-                    for n in &cell.content {
-                        visit_node(visitor, &n, existing_text)
+                        visit_nodes(visitor, atts, existing_text);
                     }
+                    visit_nodes(visitor, &cell.content, existing_text);
                 }
             }
             visitor.visit_table_end(node);
         }
         Node::UnorderedList { items, .. } | Node::OrderedList { items, .. } => {
             for item in items {
-                for n in &item.nodes {
-                    visit_node(visitor, &n, existing_text)
-                }
+                visit_nodes(visitor, &item.nodes, existing_text);
             }
         }
         Node::DefinitionList { items, .. } => {
@@ -225,8 +328,6 @@ fn visit_node(visitor: &mut impl WikiVisitor, node: &parse_wiki_text::Node, exis
                 visit_nodes(visitor, d, existing_text);
             }
         }
-
-        _ => panic!("unhandled type: {:?}", node),
     }
 }
 
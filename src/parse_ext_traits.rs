@@ -5,6 +5,11 @@ pub trait NodeExt {
     fn range(&self) -> std::ops::Range<usize>;
     fn start(&self) -> usize;
     fn end(&self) -> usize;
+    /// Variant name, e.g. `"Template"`.
+    fn kind(&self) -> &'static str;
+    /// Direct child nodes in source order, including template parameter names
+    /// and values, table attributes, captions and cells.
+    fn children(&self) -> Vec<&Node<'_>>;
 }
 impl NodeExt for parse_wiki_text::Node<'_> {
     fn as_str<'a>(&self, base_text: &'a str) -> &'a str {
@@ -19,9 +24,24 @@ impl NodeExt for parse_wiki_text::Node<'_> {
             | Node::StartTag { start, end, .. }
             | Node::Bold { start, end, .. }
             | Node::EndTag { start, end, .. }
-            | Node::Heading { start, end, .. } => *start..*end,
-
-            _ => unimplemented!("Node: {:?}", self),
+            | Node::Heading { start, end, .. }
+            | Node::Italic { start, end, .. }
+            | Node::BoldItalic { start, end, .. }
+            | Node::Category { start, end, .. }
+            | Node::CharacterEntity { start, end, .. }
+            | Node::DefinitionList { start, end, .. }
+            | Node::ExternalLink { start, end, .. }
+            | Node::HorizontalDivider { start, end, .. }
+            | Node::Image { start, end, .. }
+            | Node::MagicWord { start, end, .. }
+            | Node::OrderedList { start, end, .. }
+            | Node::UnorderedList { start, end, .. }
+            | Node::ParagraphBreak { start, end, .. }
+            | Node::Parameter { start, end, .. }
+            | Node::Preformatted { start, end, .. }
+            | Node::Redirect { start, end, .. }
+            | Node::Table { start, end, .. }
+            | Node::Tag { start, end, .. } => *start..*end,
         }
     }
     fn start(&self) -> usize {
@@ -30,6 +50,104 @@ impl NodeExt for parse_wiki_text::Node<'_> {
     fn end(&self) -> usize {
         self.range().end
     }
+    fn kind(&self) -> &'static str {
+        match self {
+            Node::Bold { .. } => "Bold",
+            Node::BoldItalic { .. } => "BoldItalic",
+            Node::Category { .. } => "Category",
+            Node::CharacterEntity { .. } => "CharacterEntity",
+            Node::Comment { .. } => "Comment",
+            Node::DefinitionList { .. } => "DefinitionList",
+            Node::EndTag { .. } => "EndTag",
+            Node::ExternalLink { .. } => "ExternalLink",
+            Node::Heading { .. } => "Heading",
+            Node::HorizontalDivider { .. } => "HorizontalDivider",
+            Node::Image { .. } => "Image",
+            Node::Italic { .. } => "Italic",
+            Node::Link { .. } => "Link",
+            Node::MagicWord { .. } => "MagicWord",
+            Node::OrderedList { .. } => "OrderedList",
+            Node::ParagraphBreak { .. } => "ParagraphBreak",
+            Node::Parameter { .. } => "Parameter",
+            Node::Preformatted { .. } => "Preformatted",
+            Node::Redirect { .. } => "Redirect",
+            Node::StartTag { .. } => "StartTag",
+            Node::Table { .. } => "Table",
+            Node::Tag { .. } => "Tag",
+            Node::Template { .. } => "Template",
+            Node::Text { .. } => "Text",
+            Node::UnorderedList { .. } => "UnorderedList",
+        }
+    }
+    fn children(&self) -> Vec<&Node<'_>> {
+        let mut out = vec![];
+        match self {
+            Node::Template {
+                name, parameters, ..
+            } => {
+                out.extend(name);
+                for param in parameters {
+                    if let Some(ns) = &param.name {
+                        out.extend(ns);
+                    }
+                    out.extend(&param.value);
+                }
+            }
+            Node::Heading { nodes, .. }
+            | Node::Tag { nodes, .. }
+            | Node::Link { text: nodes, .. }
+            | Node::Preformatted { nodes, .. }
+            | Node::ExternalLink { nodes, .. }
+            | Node::Image { text: nodes, .. }
+            | Node::Category { ordinal: nodes, .. } => out.extend(nodes),
+            Node::Table {
+                attributes,
+                captions,
+                rows,
+                ..
+            } => {
+                out.extend(attributes);
+                for cap in captions {
+                    out.extend(cap.attributes.iter().flatten());
+                    out.extend(&cap.content);
+                }
+                for row in rows {
+                    out.extend(&row.attributes);
+                    for cell in &row.cells {
+                        out.extend(cell.attributes.iter().flatten());
+                        out.extend(&cell.content);
+                    }
+                }
+            }
+            Node::UnorderedList { items, .. } | Node::OrderedList { items, .. } => {
+                for item in items {
+                    out.extend(&item.nodes);
+                }
+            }
+            Node::DefinitionList { items, .. } => {
+                for item in items {
+                    out.extend(&item.nodes);
+                }
+            }
+            Node::Parameter { default, name, .. } => {
+                out.extend(name);
+                out.extend(default.iter().flatten());
+            }
+            Node::Redirect { .. }
+            | Node::MagicWord { .. }
+            | Node::StartTag { .. }
+            | Node::Text { .. }
+            | Node::EndTag { .. }
+            | Node::Comment { .. }
+            | Node::ParagraphBreak { .. }
+            | Node::HorizontalDivider { .. }
+            | Node::Italic { .. }
+            | Node::Bold { .. }
+            | Node::CharacterEntity { .. }
+            | Node::BoldItalic { .. } => {}
+        }
+        out
+    }
 }
 
 pub trait ParameterExt {
@@ -47,7 +165,7 @@ impl ParameterExt for parse_wiki_text::Parameter<'_> {
             ..self.name.as_ref().unwrap().last().unwrap().end()]
     }
     fn val_str<'a>(&'_ self, source: &'a str) -> &'a str {
-        if self.value.len() == 0 {
+        if self.value.is_empty() {
             ""
         } else {
             let first = self.value.first().unwrap();
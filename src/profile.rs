@@ -0,0 +1,91 @@
+//! Which wiki to talk to, and how. Profiles live in `data/profiles.json5`.
+
+use crate::api::{Assert, Credentials, Session};
+use crate::transform::read_json5;
+use anyhow::Context;
+use std::collections::BTreeMap;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Profile {
+    /// `api.php` of the wiki.
+    pub api: String,
+    pub user_agent: String,
+    /// Milliseconds between edits.
+    #[serde(default)]
+    pub edit_delay: Option<u64>,
+    /// json5 file with `name` and `password`, used when neither `--creds` nor
+    /// the environment give any.
+    #[serde(default)]
+    pub creds: Option<String>,
+    /// Sent with every edit so that a lost session fails instead of editing
+    /// as an IP. `user` if not set.
+    #[serde(default)]
+    pub assert: Option<Assert>,
+    /// Nothing edits this wiki unless set.
+    #[serde(default)]
+    pub writable: bool,
+}
+
+impl Profile {
+    pub fn load(path: &str, name: &str) -> anyhow::Result<Self> {
+        let mut profiles: BTreeMap<String, Profile> = read_json5(path)?;
+        let known = profiles.keys().cloned().collect::<Vec<_>>().join(", ");
+        profiles
+            .remove(name)
+            .with_context(|| format!("no profile {:?} in {}, known: {}", name, path, known))
+    }
+
+    pub async fn connect(&self) -> anyhow::Result<mediawiki::api::Api> {
+        let mut api = mediawiki::api::Api::new(&self.api)
+            .await
+            .with_context(|| format!("connecting to {}", self.api))?;
+        api.set_user_agent(&self.user_agent);
+        api.set_edit_delay(self.edit_delay);
+        Ok(api)
+    }
+
+    /// Logs in with the first credentials found in `creds_flag`, the
+    /// `BOTTO_NAME`/`BOTTO_PASSWORD` environment variables or the profile's
+    /// file. Without any the session can only read, which is an error if
+    /// `editing`.
+    pub async fn session(
+        &self,
+        api: &mut mediawiki::api::Api,
+        creds_flag: Option<&str>,
+        editing: bool,
+    ) -> anyhow::Result<Session> {
+        let mut session = Session {
+            token: String::new(),
+            assert: self.assert.unwrap_or(Assert::User),
+            writable: self.writable,
+            creds: self.credentials(creds_flag)?,
+        };
+        match &session.creds {
+            Some(creds) => creds.validate()?,
+            None if editing => anyhow::bail!(
+                "editing needs credentials: pass --creds, set BOTTO_NAME and BOTTO_PASSWORD, or create {}",
+                self.creds.as_deref().unwrap_or("a creds file for the profile")
+            ),
+            None => return Ok(session),
+        }
+        session.login(api).await?;
+        Ok(session)
+    }
+
+    fn credentials(&self, creds_flag: Option<&str>) -> anyhow::Result<Option<Credentials>> {
+        if let Some(path) = creds_flag {
+            return read_json5(path).map(Some);
+        }
+        match (std::env::var("BOTTO_NAME"), std::env::var("BOTTO_PASSWORD")) {
+            (Ok(name), Ok(password)) => return Ok(Some(Credentials { name, password })),
+            (Ok(_), Err(_)) | (Err(_), Ok(_)) => {
+                anyhow::bail!("set both BOTTO_NAME and BOTTO_PASSWORD, or neither")
+            }
+            (Err(_), Err(_)) => {}
+        }
+        match &self.creds {
+            Some(path) if std::path::Path::new(path).exists() => read_json5(path).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! `--recent`: only go over pages changed since the last run.
+
+use crate::api::{self, RecentChange};
+use anyhow::Context;
+
+/// What `--recent` keeps between runs.
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+pub struct State {
+    /// Timestamp of the newest change seen so far.
+    pub last_timestamp: Option<String>,
+    /// The changes at `last_timestamp`, which the next run gets again.
+    #[serde(default)]
+    pub seen: Vec<i64>,
+}
+
+impl State {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        if !std::path::Path::new(path).exists() {
+            return Ok(Self::default());
+        }
+        crate::transform::read_json5(path)
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let path = std::path::Path::new(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("writing {}", path.display()))
+    }
+}
+
+/// Which changes count.
+pub struct Filter<'a> {
+    pub prefix: Option<&'a str>,
+    pub namespace: Option<i64>,
+}
+
+impl Filter<'_> {
+    pub fn matches(&self, change: &RecentChange) -> bool {
+        self.prefix.is_none_or(|p| change.title.starts_with(p))
+    }
+}
+
+/// Where to pick up from: the timestamp of the newest of `changes` and the
+/// changes at it. Timestamps are to the second, so there may be more changes at
+/// it later, which only the `seen` ones tell apart. Without changes, that's
+/// `since` and the ones seen before.
+pub fn newest(changes: &[RecentChange], since: &str, seen: &[i64]) -> (String, Vec<i64>) {
+    match changes.last() {
+        Some(last) => {
+            let at_last = changes
+                .iter()
+                .filter(|c| c.timestamp == last.timestamp)
+                .map(|c| c.rcid)
+                .collect();
+            (last.timestamp.clone(), at_last)
+        }
+        None => (since.to_string(), seen.to_vec()),
+    }
+}
+
+/// `newest` for a first run, which starts from the wiki's newest change.
+pub async fn start(
+    api: &mediawiki::api::Api,
+    namespace: Option<i64>,
+) -> anyhow::Result<(Option<String>, Vec<i64>)> {
+    let since = match api::newest_change(api).await? {
+        Some(since) => since,
+        None => return Ok((None, vec![])),
+    };
+    let changes = api::recent_changes(api, &since, namespace).await?;
+    let (since, seen) = newest(&changes, &since, &[]);
+    Ok((Some(since), seen))
+}
+
+/// Titles in the order they were last changed, each once.
+pub fn titles(changes: &[RecentChange]) -> Vec<String> {
+    let mut titles: Vec<String> = vec![];
+    for c in changes.iter().rev() {
+        if !titles.contains(&c.title) {
+            titles.push(c.title.clone());
+        }
+    }
+    titles.reverse();
+    titles
+}
+
+/// Pages changed since the last run, and the state to save once they're done.
+///
+/// On the first run there's nothing to compare against, so unless `since` is
+/// given this only records the newest change and returns no pages.
+pub async fn pages(
+    api: &mediawiki::api::Api,
+    state_path: &str,
+    since: Option<&str>,
+    filter: &Filter<'_>,
+) -> anyhow::Result<(Vec<String>, State)> {
+    let state = State::load(state_path)?;
+    let (since, seen) = match (since, state.last_timestamp) {
+        (Some(s), _) => (s.to_string(), vec![]),
+        (None, Some(s)) => (s, state.seen),
+        (None, None) => {
+            println!("{} has no timestamp yet, starting from now", state_path);
+            let (last_timestamp, seen) = start(api, filter.namespace).await?;
+            return Ok((
+                vec![],
+                State {
+                    last_timestamp,
+                    seen,
+                },
+            ));
+        }
+    };
+    let mut changes = api::recent_changes(api, &since, filter.namespace).await?;
+    let (last_timestamp, new_seen) = newest(&changes, &since, &seen);
+    // rcstart is inclusive, so the changes at a saved timestamp come again
+    changes.retain(|c| filter.matches(c) && !seen.contains(&c.rcid));
+    let state = State {
+        last_timestamp: Some(last_timestamp),
+        seen: new_seen,
+    };
+    Ok((titles(&changes), state))
+}
@@ -0,0 +1,284 @@
+//! `--mode redirects`: follows every redirect to where it ends up, to point
+//! double redirects straight there and to report the ones that lead nowhere.
+
+use crate::api::{self, PageStatus};
+use crate::diagnostics::{Diagnostic, LineIndex, Severity};
+use crate::lint::Finding;
+use crate::parse;
+use crate::visitors::normalize_title;
+use parse_wiki_text::Node;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Where a redirect ends up.
+#[derive(Debug)]
+pub enum Resolution {
+    /// Straight to an existing page, or somewhere we couldn't look at.
+    Fine,
+    /// Through the redirects in `via` to `target`, anchor included.
+    Double { via: Vec<String>, target: String },
+    /// To a page that doesn't exist, maybe through others.
+    Broken { via: Vec<String>, missing: String },
+    /// Back to a redirect already passed.
+    Loop { via: Vec<String> },
+}
+
+/// The target of a redirect page, as written, and the span of the redirect.
+pub fn redirect_target(text: &str) -> Option<(String, std::ops::Range<usize>)> {
+    let parsed = parse::parse_text(text);
+    match parsed.nodes.first()? {
+        Node::Redirect { target, start, end } => Some((target.trim().to_string(), *start..*end)),
+        _ => None,
+    }
+}
+
+/// Normalized title and `#anchor`, if any.
+fn split_target(target: &str) -> (String, Option<String>) {
+    match target.split_once('#') {
+        Some((title, anchor)) => (normalize_title(title), Some(anchor.to_string())),
+        None => (normalize_title(target), None),
+    }
+}
+
+/// Every redirect under a prefix, and whatever their targets turned out to be.
+pub struct Redirects {
+    /// Page text of each redirect, by title.
+    pub pages: BTreeMap<String, String>,
+    /// Normalized title to target as written, for every redirect seen.
+    targets: BTreeMap<String, String>,
+    status: BTreeMap<String, PageStatus>,
+}
+
+impl Redirects {
+    /// Lists the redirects under `prefix`, then looks up their targets, and the
+    /// targets of those that are redirects too, until every chain ends.
+    pub async fn load(api: &mediawiki::api::Api, prefix: &str) -> anyhow::Result<Self> {
+        let mut pages = BTreeMap::new();
+        let mut targets = BTreeMap::new();
+        for (title, text) in api::redirect_pages(api, prefix).await? {
+            if let Some((target, _)) = redirect_target(&text) {
+                targets.insert(normalize_title(&title), target);
+            }
+            pages.insert(title, text);
+        }
+        let mut status = BTreeMap::new();
+        loop {
+            let unknown = targets
+                .values()
+                .map(|t| split_target(t).0)
+                .filter(|t| !targets.contains_key(t) && !status.contains_key(t))
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>();
+            if unknown.is_empty() {
+                break;
+            }
+            let found = api::page_status(api, &unknown).await?;
+            for title in unknown {
+                let found = found.get(&title).copied();
+                if found == Some(PageStatus::Redirect) {
+                    // outside the prefix, so not listed
+                    let target = api::get_existing_page_text(api, &title)
+                        .await?
+                        .and_then(|(_, text)| redirect_target(&text));
+                    if let Some((target, _)) = target {
+                        targets.insert(title.clone(), target);
+                    }
+                }
+                // a title the wiki didn't answer for is left as a page, to not
+                // ask again
+                status.insert(title, found.unwrap_or(PageStatus::Page));
+            }
+        }
+        Ok(Self {
+            pages,
+            targets,
+            status,
+        })
+    }
+
+    pub fn resolve(&self, title: &str) -> Resolution {
+        let start = normalize_title(title);
+        let (mut current, mut anchor) = match self.targets.get(&start) {
+            Some(target) => split_target(target),
+            None => return Resolution::Fine,
+        };
+        let mut via = vec![];
+        loop {
+            if current == start || via.contains(&current) {
+                via.push(current);
+                return Resolution::Loop { via };
+            }
+            match self.targets.get(&current) {
+                Some(next) => {
+                    let (next, next_anchor) = split_target(next);
+                    // the first redirect's anchor wins, like on the wiki
+                    anchor = anchor.or(next_anchor);
+                    via.push(std::mem::replace(&mut current, next));
+                }
+                None if self.status.get(&current) == Some(&PageStatus::Missing) => {
+                    return Resolution::Broken {
+                        via,
+                        missing: current,
+                    }
+                }
+                // a redirect we couldn't read, or a page
+                None if via.is_empty()
+                    || self.status.get(&current) == Some(&PageStatus::Redirect) =>
+                {
+                    return Resolution::Fine
+                }
+                None => {
+                    let target = match anchor {
+                        Some(anchor) => format!("{}#{}", current, anchor),
+                        None => current,
+                    };
+                    return Resolution::Double { via, target };
+                }
+            }
+        }
+    }
+}
+
+/// A finding about the redirect `title`, unless it's fine.
+pub fn finding(title: &str, text: &str, resolution: &Resolution) -> Option<Finding> {
+    let chain = |via: &[String], end: &str| {
+        let mut chain = vec![title];
+        chain.extend(via.iter().map(String::as_str));
+        chain.push(end);
+        chain.join(" -> ")
+    };
+    let (severity, code, message) = match resolution {
+        Resolution::Fine => return None,
+        Resolution::Double { via, target } => (
+            Severity::Warning,
+            "redirect/double",
+            format!("double redirect {}", chain(via, target)),
+        ),
+        Resolution::Broken { via, missing } => (
+            Severity::Error,
+            "redirect/broken",
+            format!("redirect to a missing page {}", chain(via, missing)),
+        ),
+        Resolution::Loop { via } => {
+            let (end, via) = via.split_last().unwrap();
+            (
+                Severity::Error,
+                "redirect/loop",
+                format!("redirect loop {}", chain(via, end)),
+            )
+        }
+    };
+    let span = redirect_target(text).map_or(0..0, |(_, span)| span);
+    let mut diagnostic = Diagnostic::new(severity, code, span, message);
+    diagnostic.locate(title, &LineIndex::new(text), text);
+    Some(Finding {
+        lint: "redirects",
+        diagnostic,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Redirects from `title` to `target`, with the status of the pages they
+    /// end at.
+    fn redirects(targets: &[(&str, &str)], status: &[(&str, PageStatus)]) -> Redirects {
+        Redirects {
+            pages: BTreeMap::new(),
+            targets: targets
+                .iter()
+                .map(|(t, target)| (normalize_title(t), target.to_string()))
+                .collect(),
+            status: status.iter().map(|(t, s)| (t.to_string(), *s)).collect(),
+        }
+    }
+
+    fn resolve(redirects: &Redirects, title: &str) -> String {
+        format!("{:?}", redirects.resolve(title))
+    }
+
+    #[test]
+    fn straight_to_a_page() {
+        let r = redirects(
+            &[("Sol", "GGST/Sol Badguy")],
+            &[("GGST/Sol Badguy", PageStatus::Page)],
+        );
+        assert_eq!(resolve(&r, "Sol"), "Fine");
+        assert_eq!(resolve(&r, "Not a redirect"), "Fine");
+    }
+
+    #[test]
+    fn chains() {
+        let r = redirects(
+            &[("A", "B"), ("B", "c_d"), ("C d", "E#Moves")],
+            &[("E", PageStatus::Page)],
+        );
+        assert_eq!(
+            resolve(&r, "a"),
+            r#"Double { via: ["B", "C d"], target: "E#Moves" }"#
+        );
+        assert_eq!(
+            resolve(&r, "B"),
+            r#"Double { via: ["C d"], target: "E#Moves" }"#
+        );
+        assert_eq!(resolve(&r, "C_d"), "Fine");
+    }
+
+    #[test]
+    fn the_first_anchor_wins() {
+        let r = redirects(
+            &[("A", "B#First"), ("B", "C#Second")],
+            &[("C", PageStatus::Page)],
+        );
+        assert_eq!(
+            resolve(&r, "A"),
+            r#"Double { via: ["B"], target: "C#First" }"#
+        );
+    }
+
+    #[test]
+    fn missing_targets() {
+        let r = redirects(
+            &[("A", "B"), ("B", "Gone")],
+            &[("Gone", PageStatus::Missing)],
+        );
+        assert_eq!(
+            resolve(&r, "A"),
+            r#"Broken { via: ["B"], missing: "Gone" }"#
+        );
+        assert_eq!(resolve(&r, "B"), r#"Broken { via: [], missing: "Gone" }"#);
+    }
+
+    #[test]
+    fn loops() {
+        let r = redirects(&[("A", "B"), ("B", "A")], &[]);
+        assert_eq!(resolve(&r, "A"), r#"Loop { via: ["B", "A"] }"#);
+        let r = redirects(&[("A", "B"), ("B", "C"), ("C", "B")], &[]);
+        assert_eq!(resolve(&r, "A"), r#"Loop { via: ["B", "C", "B"] }"#);
+        let r = redirects(&[("A", "a")], &[]);
+        assert_eq!(resolve(&r, "A"), r#"Loop { via: ["A"] }"#);
+    }
+
+    #[test]
+    fn unreadable_redirects_are_left_alone() {
+        let r = redirects(&[("A", "B"), ("B", "C")], &[("C", PageStatus::Redirect)]);
+        assert_eq!(resolve(&r, "A"), "Fine");
+    }
+
+    #[test]
+    fn findings() {
+        let text = "#REDIRECT [[B]]";
+        assert_eq!(
+            redirect_target(text),
+            Some(("B".to_string(), 0..text.len()))
+        );
+        assert!(finding("A", text, &Resolution::Fine).is_none());
+        let loop_ = Resolution::Loop {
+            via: vec!["B".to_string(), "A".to_string()],
+        };
+        let found = finding("A", text, &loop_).unwrap();
+        assert_eq!(found.diagnostic.code, "redirect/loop");
+        assert_eq!(found.diagnostic.message, "redirect loop A -> B -> A");
+    }
+}
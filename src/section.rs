@@ -0,0 +1,202 @@
+//! Submitting only the section an edit touches, with `section=N`.
+
+/// A section heading as the wiki counts them, from `action=parse&prop=sections`.
+/// Every heading at the start of a line has a number, tables or not, which our
+/// own parse can't tell.
+pub struct Heading {
+    /// `section=` of the section it starts.
+    pub index: usize,
+    pub level: usize,
+    /// Byte offset in the page text.
+    pub offset: usize,
+}
+
+/// What to send instead of the whole page.
+pub struct SectionEdit {
+    /// `section=`, 0 being the text before the first heading.
+    pub index: usize,
+    /// Heading text, for the `/* ... */` summary prefix.
+    pub title: Option<String>,
+    pub text: String,
+}
+
+/// A section of the page being edited.
+struct Section {
+    index: usize,
+    /// Subsections included, same as with the wiki's own section edit links.
+    range: std::ops::Range<usize>,
+    title: Option<String>,
+}
+
+/// Every section the wiki numbers, or `None` if the headings don't fit `text`.
+fn sections(text: &str, headings: &[Heading]) -> Option<Vec<Section>> {
+    let fits = headings.windows(2).all(|w| w[0].offset < w[1].offset)
+        && headings
+            .iter()
+            .all(|h| text.is_char_boundary(h.offset) && text[h.offset..].starts_with('='));
+    if !fits {
+        return None;
+    }
+    let mut out = vec![Section {
+        index: 0,
+        range: 0..headings.first().map_or(text.len(), |h| h.offset),
+        title: None,
+    }];
+    for (i, heading) in headings.iter().enumerate() {
+        let end = headings[i + 1..]
+            .iter()
+            .find(|h| h.level <= heading.level)
+            .map_or(text.len(), |h| h.offset);
+        let line = text[heading.offset..].lines().next().unwrap_or("");
+        let title = line.trim().trim_matches('=').trim().to_string();
+        out.push(Section {
+            index: heading.index,
+            range: heading.offset..end,
+            title: Some(title),
+        });
+    }
+    Some(out)
+}
+
+/// The page the wiki ends up with when `new_section` replaces `range`: it
+/// drops the section's trailing whitespace, puts a blank line before whatever
+/// follows, and trims the end of the page.
+fn splice(old: &str, range: std::ops::Range<usize>, new_section: &str) -> String {
+    let mut out = old[..range.start].to_string();
+    out += new_section.trim_end();
+    if range.end < old.len() {
+        out += "\n\n";
+        out += &old[range.end..];
+    }
+    out.trim_end().to_string()
+}
+
+/// The smallest section that holds every difference between `old` and `new`,
+/// if sending just that section gets the wiki to exactly `new`. `headings` are
+/// the wiki's for `old`.
+pub fn single_section(old: &str, new: &str, headings: &[Heading]) -> Option<SectionEdit> {
+    let prefix = old
+        .bytes()
+        .zip(new.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let suffix = old
+        .bytes()
+        .rev()
+        .zip(new.bytes().rev())
+        .take(max_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let changed = prefix..(old.len() - suffix);
+
+    let Section {
+        index,
+        range,
+        title,
+    } = sections(old, headings)?
+        .into_iter()
+        .filter(|s| s.range.start <= changed.start && changed.end <= s.range.end)
+        .min_by_key(|s| s.range.len())?;
+    if range == (0..old.len()) {
+        return None;
+    }
+    // the section in `new` is the same range, moved by however much it grew
+    let new_end = range.end + new.len() - old.len();
+    let text = new.get(range.start..new_end)?.to_string();
+    if splice(old, range, &text) != new.trim_end() {
+        return None;
+    }
+    Some(SectionEdit { index, title, text })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Headings at the start of lines, numbered the way the wiki does.
+    fn headings(text: &str) -> Vec<Heading> {
+        let mut out = vec![];
+        let mut offset = 0;
+        for line in text.split_inclusive('\n') {
+            if line.starts_with('=') {
+                out.push(Heading {
+                    index: out.len() + 1,
+                    level: line.chars().take_while(|c| *c == '=').count(),
+                    offset,
+                });
+            }
+            offset += line.len();
+        }
+        out
+    }
+
+    const PAGE: &str = "lead\n\n== A ==\na\n\n=== A1 ===\na1\n\n== B ==\nb";
+
+    fn edit(old: &str, new: &str) -> Option<(usize, Option<String>, String)> {
+        single_section(old, new, &headings(old)).map(|e| (e.index, e.title, e.text))
+    }
+
+    #[test]
+    fn picks_the_smallest_section() {
+        let new = PAGE.replace("a1\n", "a1!\n");
+        let (index, title, text) = edit(PAGE, &new).unwrap();
+        assert_eq!((index, title.as_deref()), (2, Some("A1")));
+        assert_eq!(text, "=== A1 ===\na1!\n\n");
+
+        let new = PAGE.replace("\nb", "\nb!");
+        assert_eq!(edit(PAGE, &new).unwrap().0, 3);
+    }
+
+    #[test]
+    fn a_section_holds_its_subsections() {
+        let new = PAGE.replace("a\n", "a!\n").replace("a1\n", "a1!\n");
+        let (index, title, text) = edit(PAGE, &new).unwrap();
+        assert_eq!((index, title.as_deref()), (1, Some("A")));
+        assert_eq!(text, "== A ==\na!\n\n=== A1 ===\na1!\n\n");
+    }
+
+    #[test]
+    fn the_lead_is_section_zero() {
+        let new = PAGE.replace("lead", "the lead");
+        let (index, title, text) = edit(PAGE, &new).unwrap();
+        assert_eq!((index, title), (0, None));
+        assert_eq!(text, "the lead\n\n");
+    }
+
+    #[test]
+    fn changes_in_several_top_sections_send_the_page() {
+        let new = PAGE.replace("lead", "the lead").replace("\nb", "\nb!");
+        assert!(edit(PAGE, &new).is_none());
+        let new = "no headings!";
+        assert!(edit("no headings", new).is_none());
+    }
+
+    #[test]
+    fn whitespace_the_wiki_would_undo_sends_the_page() {
+        // the wiki puts a blank line back between sections
+        let new = PAGE.replace("a1\n\n", "a1\n");
+        assert!(edit(PAGE, &new).is_none());
+    }
+
+    #[test]
+    fn headings_that_dont_fit_send_the_page() {
+        let new = PAGE.replace("a1\n", "a1!\n");
+        let mut wrong = headings(PAGE);
+        wrong[1].offset -= 1;
+        assert!(single_section(PAGE, &new, &wrong).is_none());
+    }
+
+    #[test]
+    fn splice_normalizes_like_the_wiki() {
+        let old = "== A ==\na\n\n== B ==\nb\n";
+        assert_eq!(
+            splice(old, 0..11, "== A ==\nx\n\n\n"),
+            "== A ==\nx\n\n== B ==\nb"
+        );
+        assert_eq!(
+            splice(old, 11..old.len(), "== B ==\ny"),
+            "== A ==\na\n\n== B ==\ny"
+        );
+    }
+}
@@ -0,0 +1,662 @@
+//! CSS-like selectors over the parsed page.
+//!
+//! ```text
+//! template[name=clr] > param[1]
+//! table row cell[col=combo]
+//! heading[level=3] ~ template[name=CloseCard]
+//! template[name^=GGST], link[target*="#"]
+//! ```
+//!
+//! Element types are node kinds in snake case (`template`, `heading`, `link`,
+//! `external_link`, `start_tag`, ...), plus `param`, `row`, `cell`, `caption` and
+//! `item` for the parts of templates, tables and lists. `*` matches anything.
+//!
+//! Attributes are compared as strings with `=`, `!=`, `^=`, `$=`, `*=` or `~=`
+//! (regex). A bare `[x]` is short for `[name=x]`. Every element has `text`, its
+//! whole source; the rest depend on the type:
+//! - `template`: `name`
+//! - `param`: `name` (1-based position for positional ones), `value`
+//! - `heading`: `level`, `title`
+//! - `link`, `image`, `category`, `redirect`: `target`
+//! - `external_link`: `url`
+//! - `tag`, `start_tag`, `end_tag`: `name`
+//! - `row`: `index` (1-based)
+//! - `cell`: `index` (1-based column), `col` (lowercased header caption), `heading`
+//!
+//! Combinators are ` ` (descendant), `>` (child) and `~` (later sibling).
+
+use crate::prelude::*;
+use crate::table_grid::TableGrid;
+use parse_wiki_text::Node;
+use std::ops::Range;
+
+#[derive(Debug, Clone)]
+pub struct Selector {
+    /// Alternatives separated by `,`.
+    alternatives: Vec<Vec<Step>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child,
+    Sibling,
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    /// How this step relates to the previous one. Ignored for the first step.
+    combinator: Combinator,
+    kind: Option<String>,
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone)]
+struct Filter {
+    attr: String,
+    op: Op,
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Eq(String),
+    NotEq(String),
+    Prefix(String),
+    Suffix(String),
+    Contains(String),
+    Regex(regex::Regex),
+}
+
+impl Filter {
+    fn matches(&self, value: Option<&str>) -> bool {
+        let value = match value {
+            Some(v) => v,
+            None => return matches!(self.op, Op::NotEq(_)),
+        };
+        match &self.op {
+            Op::Eq(s) => value == s,
+            Op::NotEq(s) => value != s,
+            Op::Prefix(s) => value.starts_with(s.as_str()),
+            Op::Suffix(s) => value.ends_with(s.as_str()),
+            Op::Contains(s) => value.contains(s.as_str()),
+            Op::Regex(re) => re.is_match(value),
+        }
+    }
+}
+
+struct Parser<'s> {
+    s: &'s str,
+    pos: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn peek(&self) -> Option<char> {
+        self.s[self.pos..].chars().next()
+    }
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+    /// Returns whether there was any whitespace.
+    fn skip_ws(&mut self) -> bool {
+        let start = self.pos;
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+        self.pos != start
+    }
+    fn ident(&mut self) -> &'s str {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        {
+            self.bump();
+        }
+        &self.s[start..self.pos]
+    }
+    fn fail<T>(&self, what: &str) -> anyhow::Result<T> {
+        anyhow::bail!("{} at {} in selector {:?}", what, self.pos, self.s)
+    }
+
+    fn value(&mut self) -> anyhow::Result<String> {
+        self.skip_ws();
+        match self.peek() {
+            Some(q @ ('"' | '\'')) => {
+                self.bump();
+                let start = self.pos;
+                loop {
+                    match self.bump() {
+                        Some(c) if c == q => break,
+                        Some(_) => {}
+                        None => return self.fail("unterminated string"),
+                    }
+                }
+                Ok(self.s[start..self.pos - 1].to_string())
+            }
+            _ => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c != ']') {
+                    self.bump();
+                }
+                Ok(self.s[start..self.pos].trim().to_string())
+            }
+        }
+    }
+
+    fn filter(&mut self) -> anyhow::Result<Filter> {
+        self.skip_ws();
+        let attr = self.ident().to_string();
+        self.skip_ws();
+        let op = match self.peek() {
+            Some(']') => {
+                // `[x]` is `[name=x]`
+                self.bump();
+                return Ok(Filter {
+                    attr: "name".to_string(),
+                    op: Op::Eq(attr),
+                });
+            }
+            Some('=') => "=",
+            Some('!' | '^' | '$' | '*' | '~') => {
+                let op = &self.s[self.pos..self.pos + 1];
+                self.bump();
+                if self.peek() != Some('=') {
+                    return self.fail("expected `=`");
+                }
+                op
+            }
+            _ => return self.fail("expected an operator or `]`"),
+        };
+        if attr.is_empty() {
+            return self.fail("expected an attribute name");
+        }
+        self.bump(); // `=`
+        let value = self.value()?;
+        self.skip_ws();
+        if self.bump() != Some(']') {
+            return self.fail("expected `]`");
+        }
+        let op = match op {
+            "=" => Op::Eq(value),
+            "!" => Op::NotEq(value),
+            "^" => Op::Prefix(value),
+            "$" => Op::Suffix(value),
+            "*" => Op::Contains(value),
+            "~" => Op::Regex(regex::Regex::new(&value)?),
+            _ => unreachable!(),
+        };
+        Ok(Filter { attr, op })
+    }
+
+    fn step(&mut self, combinator: Combinator) -> anyhow::Result<Step> {
+        let any = self.peek() == Some('*');
+        if any {
+            self.bump();
+        }
+        let kind = match self.ident() {
+            "" => None,
+            k => Some(k.to_string()),
+        };
+        let mut filters = vec![];
+        while self.peek() == Some('[') {
+            self.bump();
+            filters.push(self.filter()?);
+        }
+        if kind.is_none() && filters.is_empty() && !any {
+            return self.fail("expected an element type or `[`");
+        }
+        Ok(Step {
+            combinator,
+            kind,
+            filters,
+        })
+    }
+
+    fn selector(&mut self) -> anyhow::Result<Selector> {
+        let mut alternatives = vec![];
+        loop {
+            self.skip_ws();
+            let mut steps = vec![self.step(Combinator::Descendant)?];
+            loop {
+                let had_ws = self.skip_ws();
+                let combinator = match self.peek() {
+                    None | Some(',') => break,
+                    Some('>') => Combinator::Child,
+                    Some('~') => Combinator::Sibling,
+                    _ if had_ws => Combinator::Descendant,
+                    _ => return self.fail("unexpected character"),
+                };
+                if combinator != Combinator::Descendant {
+                    self.bump();
+                    self.skip_ws();
+                }
+                steps.push(self.step(combinator)?);
+            }
+            alternatives.push(steps);
+            if self.bump().is_none() {
+                break;
+            }
+        }
+        Ok(Selector { alternatives })
+    }
+}
+
+impl std::str::FromStr for Selector {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Parser { s, pos: 0 }.selector()
+    }
+}
+
+impl Selector {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+/// A node, or a part of one (`param`, `row`, `cell`, ...), in the selector tree.
+pub struct Element<'n> {
+    pub kind: &'static str,
+    pub range: Range<usize>,
+    /// `None` for the parts that aren't nodes of their own.
+    pub node: Option<&'n Node<'n>>,
+    /// For `text`, which is sliced out of it when asked for.
+    source: &'n str,
+    attributes: Vec<(&'static str, String)>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+impl Element<'_> {
+    /// `text` and the type-specific attributes listed in the module docs.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        if name == "text" {
+            return Some(&self.source[self.range.clone()]);
+        }
+        self.attributes
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// All elements of a page, for matching selectors against.
+pub struct Tree<'n> {
+    source: &'n str,
+    elements: Vec<Element<'n>>,
+    roots: Vec<usize>,
+}
+
+fn snake_case(kind: &str) -> &'static str {
+    match kind {
+        "BoldItalic" => "bold_italic",
+        "CharacterEntity" => "character_entity",
+        "DefinitionList" => "definition_list",
+        "EndTag" => "end_tag",
+        "ExternalLink" => "external_link",
+        "HorizontalDivider" => "horizontal_divider",
+        "MagicWord" => "magic_word",
+        "OrderedList" => "ordered_list",
+        "ParagraphBreak" => "paragraph_break",
+        "StartTag" => "start_tag",
+        "UnorderedList" => "unordered_list",
+        "Bold" => "bold",
+        "Category" => "category",
+        "Comment" => "comment",
+        "Heading" => "heading",
+        "Image" => "image",
+        "Italic" => "italic",
+        "Link" => "link",
+        "Parameter" => "parameter",
+        "Preformatted" => "preformatted",
+        "Redirect" => "redirect",
+        "Table" => "table",
+        "Tag" => "tag",
+        "Template" => "template",
+        "Text" => "text",
+        _ => unreachable!("{}", kind),
+    }
+}
+
+impl<'n> Tree<'n> {
+    pub fn new(nodes: &'n [Node<'n>], source: &'n str) -> Self {
+        let mut tree = Tree {
+            source,
+            elements: vec![],
+            roots: vec![],
+        };
+        for node in nodes {
+            tree.add_node(node, None);
+        }
+        tree
+    }
+
+    fn push(
+        &mut self,
+        kind: &'static str,
+        range: Range<usize>,
+        node: Option<&'n Node<'n>>,
+        attributes: Vec<(&'static str, String)>,
+        parent: Option<usize>,
+    ) -> usize {
+        let index = self.elements.len();
+        self.elements.push(Element {
+            kind,
+            range,
+            node,
+            source: self.source,
+            attributes,
+            parent,
+            children: vec![],
+        });
+        match parent {
+            Some(p) => self.elements[p].children.push(index),
+            None => self.roots.push(index),
+        }
+        index
+    }
+
+    fn add_nodes(&mut self, nodes: &'n [Node<'n>], parent: usize) {
+        for node in nodes {
+            self.add_node(node, Some(parent));
+        }
+    }
+
+    fn add_node(&mut self, node: &'n Node<'n>, parent: Option<usize>) {
+        let source = self.source;
+        let mut attributes = vec![];
+        match node {
+            Node::Template { name, .. } => {
+                attributes.push(("name", name.as_str(source).trim().to_string()))
+            }
+            Node::Heading { level, nodes, .. } => {
+                attributes.push(("level", level.to_string()));
+                attributes.push(("title", nodes.as_str(source).trim().to_string()));
+            }
+            Node::Link { target, .. }
+            | Node::Image { target, .. }
+            | Node::Category { target, .. }
+            | Node::Redirect { target, .. } => attributes.push(("target", target.to_string())),
+            Node::ExternalLink { nodes, .. } => {
+                let inner = nodes.as_str(source);
+                let url = inner.split_whitespace().next().unwrap_or("");
+                attributes.push(("url", url.to_string()));
+            }
+            Node::Tag { name, .. } | Node::StartTag { name, .. } | Node::EndTag { name, .. } => {
+                attributes.push(("name", name.to_string()))
+            }
+            _ => {}
+        }
+        let me = self.push(
+            snake_case(node.kind()),
+            node.range(),
+            Some(node),
+            attributes,
+            parent,
+        );
+        match node {
+            Node::Template { parameters, .. } => {
+                let mut position = 0;
+                for param in parameters {
+                    let name = match &param.name {
+                        Some(_) => param.name_str(source).to_string(),
+                        None => {
+                            position += 1;
+                            position.to_string()
+                        }
+                    };
+                    let attributes = vec![
+                        ("name", name),
+                        ("value", param.val_str(source).trim().to_string()),
+                    ];
+                    let p = self.push("param", param.start..param.end, None, attributes, Some(me));
+                    self.add_nodes(&param.value, p);
+                }
+            }
+            Node::Table { captions, rows, .. } => {
+                let grid = TableGrid::new(node, source).unwrap();
+                for caption in captions {
+                    let c = self.push(
+                        "caption",
+                        caption.start..caption.end,
+                        None,
+                        vec![],
+                        Some(me),
+                    );
+                    self.add_nodes(&caption.content, c);
+                }
+                for (i, row) in rows.iter().enumerate() {
+                    let r = self.push(
+                        "row",
+                        row.start..row.end,
+                        None,
+                        vec![("index", (i + 1).to_string())],
+                        Some(me),
+                    );
+                    for cell in &row.cells {
+                        let mut attributes = vec![(
+                            "heading",
+                            (cell.type_ == parse_wiki_text::TableCellType::Heading).to_string(),
+                        )];
+                        let grid_cell = grid.cells().iter().find(|g| g.range.start == cell.start);
+                        if let Some(g) = grid_cell {
+                            attributes.push(("index", (g.col + 1).to_string()));
+                            if let Some(caption) = grid.columns().get(g.col) {
+                                attributes.push(("col", caption.clone()));
+                            }
+                        }
+                        let c = self.push("cell", cell.start..cell.end, None, attributes, Some(r));
+                        self.add_nodes(&cell.content, c);
+                    }
+                }
+            }
+            Node::UnorderedList { items, .. } | Node::OrderedList { items, .. } => {
+                for item in items {
+                    let i = self.push("item", item.start..item.end, None, vec![], Some(me));
+                    self.add_nodes(&item.nodes, i);
+                }
+            }
+            Node::DefinitionList { items, .. } => {
+                for item in items {
+                    let i = self.push("item", item.start..item.end, None, vec![], Some(me));
+                    self.add_nodes(&item.nodes, i);
+                }
+            }
+            Node::Heading { nodes, .. }
+            | Node::Tag { nodes, .. }
+            | Node::Preformatted { nodes, .. }
+            | Node::ExternalLink { nodes, .. }
+            | Node::Link { text: nodes, .. }
+            | Node::Image { text: nodes, .. } => self.add_nodes(nodes, me),
+            _ => {}
+        }
+    }
+
+    pub fn source(&self) -> &'n str {
+        self.source
+    }
+
+    pub fn elements(&self) -> &[Element<'n>] {
+        &self.elements
+    }
+
+    /// Matching elements, in source order.
+    pub fn select(&self, selector: &Selector) -> Vec<&Element<'n>> {
+        (0..self.elements.len())
+            .filter(|e| {
+                selector
+                    .alternatives
+                    .iter()
+                    .any(|steps| self.matches(*e, steps))
+            })
+            .map(|e| &self.elements[e])
+            .collect()
+    }
+
+    fn step_matches(&self, element: usize, step: &Step) -> bool {
+        let element = &self.elements[element];
+        if let Some(kind) = &step.kind {
+            if kind != element.kind {
+                return false;
+            }
+        }
+        step.filters
+            .iter()
+            .all(|f| f.matches(element.attribute(&f.attr)))
+    }
+
+    /// Whether `element` matches the last of `steps`, with the rest matching around it.
+    fn matches(&self, element: usize, steps: &[Step]) -> bool {
+        let (last, rest) = match steps.split_last() {
+            Some(s) => s,
+            None => return true,
+        };
+        if !self.step_matches(element, last) {
+            return false;
+        }
+        if rest.is_empty() {
+            return true;
+        }
+        let parent = self.elements[element].parent;
+        match last.combinator {
+            Combinator::Child => parent.is_some_and(|p| self.matches(p, rest)),
+            Combinator::Descendant => {
+                let mut ancestor = parent;
+                while let Some(a) = ancestor {
+                    if self.matches(a, rest) {
+                        return true;
+                    }
+                    ancestor = self.elements[a].parent;
+                }
+                false
+            }
+            Combinator::Sibling => {
+                let siblings = match parent {
+                    Some(p) => &self.elements[p].children,
+                    None => &self.roots,
+                };
+                siblings
+                    .iter()
+                    .take_while(|s| **s != element)
+                    .any(|s| self.matches(*s, rest))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    /// Source of every element `selector` picks out of `text`.
+    fn select(text: &str, selector: &str) -> Vec<String> {
+        let parsed = parse::parse_text(text);
+        let tree = Tree::new(&parsed.nodes, text);
+        tree.select(&Selector::parse(selector).unwrap())
+            .into_iter()
+            .map(|e| text[e.range.clone()].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn parses() {
+        for s in [
+            "template",
+            "*",
+            "[clr]",
+            "template[name=clr] > param[1]",
+            "table row cell[col=combo]",
+            "heading[level=3] ~ template[name=CloseCard]",
+            "template[name^=GGST], link[target*=\"#\"]",
+            "link[target~='^GGST/.*$'][target!=x]",
+            "  template  >  param  ",
+        ] {
+            assert!(Selector::parse(s).is_ok(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn rejects() {
+        for s in [
+            "",
+            "template[",
+            "template[name]]",
+            "template[=x]",
+            "template[name<x]",
+            "template[name=\"x]",
+            "template[name~=(]",
+            "template,",
+            "template >",
+            "template+param",
+        ] {
+            assert!(Selector::parse(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn kinds_and_attributes() {
+        let text = "{{clr|1|5K}} {{GGST-Move|x}} [[GGST/Sol#Moves|Sol]] [[Other]]";
+        assert_eq!(select(text, "template[clr]"), ["{{clr|1|5K}}"]);
+        assert_eq!(select(text, "template[name^=GGST]"), ["{{GGST-Move|x}}"]);
+        assert_eq!(
+            select(text, "link[target*=\"#\"]"),
+            ["[[GGST/Sol#Moves|Sol]]"]
+        );
+        assert_eq!(select(text, "link[target~='^O']"), ["[[Other]]"]);
+        assert_eq!(select(text, "template[name!=clr]"), ["{{GGST-Move|x}}"]);
+        assert_eq!(
+            select(text, "template[clr], link[target$=er]"),
+            ["{{clr|1|5K}}", "[[Other]]"]
+        );
+    }
+
+    #[test]
+    fn missing_attributes_only_match_not_equal() {
+        let text = "[[Sol]]";
+        assert!(select(text, "link[level=1]").is_empty());
+        assert_eq!(select(text, "link[level!=1]"), ["[[Sol]]"]);
+    }
+
+    #[test]
+    fn params() {
+        let text = "{{clr|1|5K|note = x}}";
+        assert_eq!(select(text, "template[clr] > param[1]"), ["1"]);
+        assert_eq!(select(text, "template[clr] > param[2]"), ["5K"]);
+        assert_eq!(select(text, "param[value=x]").len(), 1);
+        assert!(select(text, "param[3]").is_empty());
+    }
+
+    #[test]
+    fn child_and_descendant() {
+        let text = "{{Box|{{clr|1|5K}}}} {{clr|2|6P}}";
+        assert_eq!(
+            select(text, "template[Box] template[clr]"),
+            ["{{clr|1|5K}}"]
+        );
+        assert!(select(text, "template[Box] > template[clr]").is_empty());
+        assert_eq!(
+            select(text, "template[Box] > param > template[clr]"),
+            ["{{clr|1|5K}}"]
+        );
+    }
+
+    #[test]
+    fn later_siblings() {
+        let text = "{{A}}\n===X===\n{{B}}\n{{C}}";
+        assert_eq!(select(text, "heading ~ template"), ["{{B}}", "{{C}}"]);
+        assert_eq!(select(text, "template[B] ~ template"), ["{{C}}"]);
+        assert!(select(text, "template[C] ~ template").is_empty());
+    }
+
+    #[test]
+    fn table_cells_by_column() {
+        let text = "{|\n! Combo !! Damage\n|-\n| 5K > 2D || 50\n|}";
+        let cells = select(text, "table row cell[col=damage]");
+        assert_eq!(cells.len(), 2);
+        assert!(cells[1].contains("50"));
+        assert_eq!(select(text, "row[index=2] cell[index=1]").len(), 1);
+    }
+}
@@ -0,0 +1,588 @@
+use crate::diagnostics::{Diagnostic, LineIndex, Severity};
+use crate::prelude::*;
+use crate::template_view::TemplateView;
+use parse_wiki_text::Node;
+use similar::DiffTag;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// One structural difference between two versions of a page.
+///
+/// Ranges are byte ranges in the old (`old_range`) or new (`new_range`) text.
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    TemplateAdded {
+        name: String,
+        new_range: Range<usize>,
+    },
+    TemplateRemoved {
+        name: String,
+        old_range: Range<usize>,
+    },
+    TemplateChanged {
+        name: String,
+        new_name: Option<String>,
+        params: Vec<ParamChange>,
+        old_range: Range<usize>,
+        new_range: Range<usize>,
+    },
+    TableChanged {
+        /// Index among the page's tables, nested ones included.
+        table: usize,
+        rows: Vec<RowChange>,
+        old_range: Range<usize>,
+        new_range: Range<usize>,
+    },
+    TableAdded {
+        new_range: Range<usize>,
+    },
+    TableRemoved {
+        old_range: Range<usize>,
+    },
+    HeadingAdded {
+        title: String,
+        new_range: Range<usize>,
+    },
+    HeadingRemoved {
+        title: String,
+        old_range: Range<usize>,
+    },
+    HeadingMoved {
+        title: String,
+        old_range: Range<usize>,
+        new_range: Range<usize>,
+    },
+    LinkRetargeted {
+        text: String,
+        old_target: String,
+        new_target: String,
+        old_range: Range<usize>,
+        new_range: Range<usize>,
+    },
+    LinkAdded {
+        target: String,
+        new_range: Range<usize>,
+    },
+    LinkRemoved {
+        target: String,
+        old_range: Range<usize>,
+    },
+    /// Any other kind of node, counted rather than matched up.
+    Other {
+        kind: &'static str,
+        added: usize,
+        removed: usize,
+    },
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct ParamChange {
+    pub param: String,
+    /// `None` if the parameter was added
+    pub old: Option<String>,
+    /// `None` if the parameter was removed
+    pub new: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RowChange {
+    /// Row index in the old table, `None` if the row was added.
+    pub old_row: Option<usize>,
+    /// Row index in the new table, `None` if the row was removed.
+    pub new_row: Option<usize>,
+}
+
+impl Change {
+    /// Kind of node the change is about, as in `NodeExt::kind`.
+    pub fn node_kind(&self) -> &'static str {
+        match self {
+            Change::TemplateAdded { .. }
+            | Change::TemplateRemoved { .. }
+            | Change::TemplateChanged { .. } => "Template",
+            Change::TableChanged { .. }
+            | Change::TableAdded { .. }
+            | Change::TableRemoved { .. } => "Table",
+            Change::HeadingAdded { .. }
+            | Change::HeadingRemoved { .. }
+            | Change::HeadingMoved { .. } => "Heading",
+            Change::LinkRetargeted { .. }
+            | Change::LinkAdded { .. }
+            | Change::LinkRemoved { .. } => "Link",
+            Change::Other { kind, .. } => kind,
+        }
+    }
+
+    /// Where the change is in the old text, if it's there at all.
+    pub fn old_range(&self) -> Option<Range<usize>> {
+        match self {
+            Change::TemplateRemoved { old_range, .. }
+            | Change::TemplateChanged { old_range, .. }
+            | Change::TableChanged { old_range, .. }
+            | Change::TableRemoved { old_range }
+            | Change::HeadingRemoved { old_range, .. }
+            | Change::HeadingMoved { old_range, .. }
+            | Change::LinkRetargeted { old_range, .. }
+            | Change::LinkRemoved { old_range, .. } => Some(old_range.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn render(&self) -> String {
+        match self {
+            Change::TemplateAdded { name, .. } => format!("+ template {{{{{}}}}}", name),
+            Change::TemplateRemoved { name, .. } => format!("- template {{{{{}}}}}", name),
+            Change::TemplateChanged {
+                name,
+                new_name,
+                params,
+                ..
+            } => {
+                let mut out = format!("~ template {{{{{}}}}}", name);
+                if let Some(n) = new_name {
+                    out += &format!(" renamed to {{{{{}}}}}", n);
+                }
+                for p in params {
+                    out += &match (&p.old, &p.new) {
+                        (Some(o), Some(n)) => format!("\n    |{}: {:?} -> {:?}", p.param, o, n),
+                        (None, Some(n)) => format!("\n    +|{}: {:?}", p.param, n),
+                        (Some(o), None) => format!("\n    -|{}: {:?}", p.param, o),
+                        (None, None) => unreachable!(),
+                    };
+                }
+                out
+            }
+            Change::TableChanged { table, rows, .. } => {
+                let count = |f: fn(&RowChange) -> bool| rows.iter().filter(|r| f(r)).count();
+                format!(
+                    "~ table #{}: {} row(s) changed, {} added, {} removed",
+                    table,
+                    count(|r| r.old_row.is_some() && r.new_row.is_some()),
+                    count(|r| r.old_row.is_none()),
+                    count(|r| r.new_row.is_none()),
+                )
+            }
+            Change::TableAdded { .. } => "+ table".to_string(),
+            Change::TableRemoved { .. } => "- table".to_string(),
+            Change::HeadingAdded { title, .. } => format!("+ heading {:?}", title),
+            Change::HeadingRemoved { title, .. } => format!("- heading {:?}", title),
+            Change::HeadingMoved { title, .. } => format!("~ heading {:?} moved", title),
+            Change::LinkRetargeted {
+                text,
+                old_target,
+                new_target,
+                ..
+            } => format!(
+                "~ link {:?}: [[{}]] -> [[{}]]",
+                text, old_target, new_target
+            ),
+            Change::LinkAdded { target, .. } => format!("+ link [[{}]]", target),
+            Change::LinkRemoved { target, .. } => format!("- link [[{}]]", target),
+            Change::Other {
+                kind,
+                added,
+                removed,
+            } => format!("~ {}: {} added, {} removed", kind, added, removed),
+        }
+    }
+}
+
+/// Pairs up two sequences by their keys: `Some`/`Some` for items that changed,
+/// one-sided for added or removed ones. Items with equal keys are skipped entirely.
+/// Within a changed stretch, `same(old, new)` decides which items are the same thing.
+fn align(
+    old_keys: &[String],
+    new_keys: &[String],
+    same: impl Fn(usize, usize) -> bool,
+) -> Vec<(Option<usize>, Option<usize>)> {
+    let mut out = vec![];
+    for op in similar::capture_diff_slices(similar::Algorithm::Myers, old_keys, new_keys) {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        match tag {
+            DiffTag::Equal => {}
+            DiffTag::Delete => out.extend(old_range.map(|o| (Some(o), None))),
+            DiffTag::Insert => out.extend(new_range.map(|n| (None, Some(n)))),
+            DiffTag::Replace => {
+                let mut unpaired = new_range.collect::<Vec<_>>();
+                for o in old_range {
+                    match unpaired.iter().position(|n| same(o, *n)) {
+                        Some(i) => out.push((Some(o), Some(unpaired.remove(i)))),
+                        None => out.push((Some(o), None)),
+                    }
+                }
+                out.extend(unpaired.into_iter().map(|n| (None, Some(n))));
+            }
+        }
+    }
+    out
+}
+
+fn heading_title<'t>(node: &Node, text: &'t str) -> &'t str {
+    node.as_str(text).trim_matches('=').trim()
+}
+
+/// Every node inside `node`, in source order.
+fn descendants<'n>(node: &'n Node<'n>) -> Vec<&'n Node<'n>> {
+    let mut out = vec![];
+    for c in node.children() {
+        out.push(c);
+        out.extend(descendants(c));
+    }
+    out
+}
+
+/// `range` of `text`, with the outermost of `inner` that are of the `masked`
+/// kinds blanked out, so that containers only differing in those compare equal.
+fn masked(text: &str, range: Range<usize>, inner: &[&Node], masked: &[&str]) -> String {
+    let mut out = String::new();
+    let mut last = range.start;
+    for n in inner {
+        let inside = range.start <= n.start() && n.end() <= range.end;
+        if !inside || n.start() < last || !masked.contains(&n.kind()) {
+            continue;
+        }
+        // next to each other they're one blank, as a change may split or join them
+        if n.start() > last || !out.ends_with('\u{0}') {
+            out += &text[last..n.start()];
+            out.push('\u{0}');
+        }
+        last = n.end();
+    }
+    out += &text[last..range.end];
+    out
+}
+
+fn link_target<'n>(node: &Node<'n>) -> &'n str {
+    match node {
+        Node::Link { target, .. } => target,
+        _ => unreachable!(),
+    }
+}
+
+fn link_text<'t>(node: &Node, text: &'t str) -> &'t str {
+    &text[link_label(node)]
+}
+
+fn link_label(node: &Node) -> Range<usize> {
+    match node {
+        Node::Link { text: t, .. } if !t.is_empty() => t[0].start()..t[t.len() - 1].end(),
+        _ => 0..0,
+    }
+}
+
+fn param_map(view: &TemplateView) -> BTreeMap<String, String> {
+    let mut out = BTreeMap::new();
+    for (i, v) in view.positional().iter().enumerate() {
+        out.insert((i + 1).to_string(), v.to_string());
+    }
+    for (k, v) in view.named() {
+        out.insert(k.to_string(), v.to_string());
+    }
+    out
+}
+
+fn table_rows(node: &Node) -> Vec<Range<usize>> {
+    match node {
+        Node::Table { rows, .. } => rows.iter().map(|r| r.start..r.end).collect(),
+        _ => unreachable!(),
+    }
+}
+
+/// Structural diff of two versions of a page.
+pub fn diff(old_text: &str, new_text: &str) -> Vec<Change> {
+    diff_masking(old_text, new_text, &[])
+}
+
+/// `diff`, but with nodes of the `masked` kinds blanked out of the other nodes
+/// they are in: a table or heading that only changed inside them isn't one.
+fn diff_masking(old_text: &str, new_text: &str, masked_kinds: &[&str]) -> Vec<Change> {
+    let old_parsed = crate::parse::parse_text(old_text);
+    let new_parsed = crate::parse::parse_text(new_text);
+    let mut old_nodes = vec![];
+    crate::verify::flatten(&old_parsed.nodes, &mut old_nodes);
+    let mut new_nodes = vec![];
+    crate::verify::flatten(&new_parsed.nodes, &mut new_nodes);
+    let of_kind = |nodes: &[&'_ Node<'_>], kind: &str| -> Vec<usize> {
+        (0..nodes.len())
+            .filter(|i| nodes[*i].kind() == kind)
+            .collect()
+    };
+    let old_key = |node: &Node, range: Range<usize>| {
+        masked(old_text, range, &descendants(node), masked_kinds)
+    };
+    let new_key = |node: &Node, range: Range<usize>| {
+        masked(new_text, range, &descendants(node), masked_kinds)
+    };
+    let mut changes = vec![];
+
+    // templates
+    let old_t = of_kind(&old_nodes, "Template");
+    let new_t = of_kind(&new_nodes, "Template");
+    let old_views = old_t
+        .iter()
+        .map(|i| TemplateView::new(old_nodes[*i], old_text).unwrap())
+        .collect::<Vec<_>>();
+    let new_views = new_t
+        .iter()
+        .map(|i| TemplateView::new(new_nodes[*i], new_text).unwrap())
+        .collect::<Vec<_>>();
+    let pairs = align(
+        &old_t
+            .iter()
+            .map(|i| old_key(old_nodes[*i], old_nodes[*i].range()))
+            .collect::<Vec<_>>(),
+        &new_t
+            .iter()
+            .map(|i| new_key(new_nodes[*i], new_nodes[*i].range()))
+            .collect::<Vec<_>>(),
+        |o, n| old_views[o].name() == new_views[n].name(),
+    );
+    for pair in pairs {
+        match pair {
+            (Some(o), Some(n)) => {
+                let (o, n) = (&old_views[o], &new_views[n]);
+                let (old_params, new_params) = (param_map(o), param_map(n));
+                let mut params = vec![];
+                for k in old_params.keys().chain(new_params.keys()) {
+                    if params.iter().any(|p: &ParamChange| &p.param == k) {
+                        continue;
+                    }
+                    let (old, new) = (old_params.get(k), new_params.get(k));
+                    if old != new {
+                        params.push(ParamChange {
+                            param: k.clone(),
+                            old: old.cloned(),
+                            new: new.cloned(),
+                        });
+                    }
+                }
+                changes.push(Change::TemplateChanged {
+                    name: o.name().to_string(),
+                    new_name: Some(n.name().to_string()).filter(|n| n != o.name()),
+                    params,
+                    old_range: o.range(),
+                    new_range: n.range(),
+                });
+            }
+            (Some(o), None) => changes.push(Change::TemplateRemoved {
+                name: old_views[o].name().to_string(),
+                old_range: old_views[o].range(),
+            }),
+            (None, Some(n)) => changes.push(Change::TemplateAdded {
+                name: new_views[n].name().to_string(),
+                new_range: new_views[n].range(),
+            }),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    // tables, row by row
+    let old_tables = of_kind(&old_nodes, "Table")
+        .into_iter()
+        .map(|i| old_nodes[i])
+        .collect::<Vec<_>>();
+    let new_tables = of_kind(&new_nodes, "Table")
+        .into_iter()
+        .map(|i| new_nodes[i])
+        .collect::<Vec<_>>();
+    let pairs = align(
+        &old_tables
+            .iter()
+            .map(|t| old_key(t, t.range()))
+            .collect::<Vec<_>>(),
+        &new_tables
+            .iter()
+            .map(|t| new_key(t, t.range()))
+            .collect::<Vec<_>>(),
+        |_, _| true,
+    );
+    for pair in pairs {
+        match pair {
+            (Some(o), Some(n)) => {
+                let (old_table, new_table) = (old_tables[o], new_tables[n]);
+                let old_rows = table_rows(old_table)
+                    .into_iter()
+                    .map(|r| old_key(old_table, r))
+                    .collect::<Vec<_>>();
+                let new_rows = table_rows(new_table)
+                    .into_iter()
+                    .map(|r| new_key(new_table, r))
+                    .collect::<Vec<_>>();
+                let rows = align(&old_rows, &new_rows, |_, _| true)
+                    .into_iter()
+                    .map(|(old_row, new_row)| RowChange { old_row, new_row })
+                    .collect();
+                changes.push(Change::TableChanged {
+                    table: o,
+                    rows,
+                    old_range: old_tables[o].range(),
+                    new_range: new_tables[n].range(),
+                });
+            }
+            (Some(o), None) => changes.push(Change::TableRemoved {
+                old_range: old_tables[o].range(),
+            }),
+            (None, Some(n)) => changes.push(Change::TableAdded {
+                new_range: new_tables[n].range(),
+            }),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    // headings: a removal and an addition of the same title is a move
+    let old_h = of_kind(&old_nodes, "Heading");
+    let new_h = of_kind(&new_nodes, "Heading");
+    let old_titles = old_h
+        .iter()
+        .map(|i| heading_title(old_nodes[*i], old_text))
+        .collect::<Vec<_>>();
+    let new_titles = new_h
+        .iter()
+        .map(|i| heading_title(new_nodes[*i], new_text))
+        .collect::<Vec<_>>();
+    let heading_key = |key: String| key.trim_matches('=').trim().to_string();
+    let pairs = align(
+        &old_h
+            .iter()
+            .map(|i| heading_key(old_key(old_nodes[*i], old_nodes[*i].range())))
+            .collect::<Vec<_>>(),
+        &new_h
+            .iter()
+            .map(|i| heading_key(new_key(new_nodes[*i], new_nodes[*i].range())))
+            .collect::<Vec<_>>(),
+        |_, _| false,
+    );
+    let mut added = pairs.iter().filter_map(|p| p.1).collect::<Vec<_>>();
+    for o in pairs.iter().filter_map(|p| p.0) {
+        let old_range = old_nodes[old_h[o]].range();
+        match added.iter().position(|n| new_titles[*n] == old_titles[o]) {
+            Some(i) => {
+                let n = added.remove(i);
+                changes.push(Change::HeadingMoved {
+                    title: old_titles[o].to_string(),
+                    old_range,
+                    new_range: new_nodes[new_h[n]].range(),
+                });
+            }
+            None => changes.push(Change::HeadingRemoved {
+                title: old_titles[o].to_string(),
+                old_range,
+            }),
+        }
+    }
+    for n in added {
+        changes.push(Change::HeadingAdded {
+            title: new_titles[n].to_string(),
+            new_range: new_nodes[new_h[n]].range(),
+        });
+    }
+
+    // links, paired up by their displayed text
+    let old_l = of_kind(&old_nodes, "Link")
+        .into_iter()
+        .map(|i| old_nodes[i])
+        .collect::<Vec<_>>();
+    let new_l = of_kind(&new_nodes, "Link")
+        .into_iter()
+        .map(|i| new_nodes[i])
+        .collect::<Vec<_>>();
+    let old_keys = old_l
+        .iter()
+        .map(|n| (link_target(n), link_text(n, old_text)))
+        .collect::<Vec<_>>();
+    let new_keys = new_l
+        .iter()
+        .map(|n| (link_target(n), link_text(n, new_text)))
+        .collect::<Vec<_>>();
+    let link_key = |node: &Node, text: String| format!("{}|{}", link_target(node), text);
+    let pairs = align(
+        &old_l
+            .iter()
+            .map(|n| link_key(n, old_key(n, link_label(n))))
+            .collect::<Vec<_>>(),
+        &new_l
+            .iter()
+            .map(|n| link_key(n, new_key(n, link_label(n))))
+            .collect::<Vec<_>>(),
+        |o, n| old_keys[o].1 == new_keys[n].1,
+    );
+    for pair in pairs {
+        match pair {
+            (Some(o), Some(n)) => changes.push(Change::LinkRetargeted {
+                text: old_keys[o].1.to_string(),
+                old_target: old_keys[o].0.to_string(),
+                new_target: new_keys[n].0.to_string(),
+                old_range: old_l[o].range(),
+                new_range: new_l[n].range(),
+            }),
+            (Some(o), None) => changes.push(Change::LinkRemoved {
+                target: old_keys[o].0.to_string(),
+                old_range: old_l[o].range(),
+            }),
+            (None, Some(n)) => changes.push(Change::LinkAdded {
+                target: new_keys[n].0.to_string(),
+                new_range: new_l[n].range(),
+            }),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    // everything else: compare counts of (kind, text)
+    let skip = [
+        "Template",
+        "Table",
+        "Heading",
+        "Link",
+        "Text",
+        "ParagraphBreak",
+    ];
+    let mut counts: BTreeMap<(&'static str, String), isize> = BTreeMap::new();
+    for n in old_nodes.iter().filter(|n| !skip.contains(&n.kind())) {
+        *counts.entry((n.kind(), old_key(n, n.range()))).or_default() -= 1;
+    }
+    for n in new_nodes.iter().filter(|n| !skip.contains(&n.kind())) {
+        *counts.entry((n.kind(), new_key(n, n.range()))).or_default() += 1;
+    }
+    let mut other: BTreeMap<&'static str, (usize, usize)> = BTreeMap::new();
+    for ((kind, _), count) in counts {
+        let slot = other.entry(kind).or_default();
+        if count > 0 {
+            slot.0 += count as usize;
+        } else {
+            slot.1 += (-count) as usize;
+        }
+    }
+    for (kind, (added, removed)) in other {
+        if added + removed > 0 {
+            changes.push(Change::Other {
+                kind,
+                added,
+                removed,
+            });
+        }
+    }
+    changes
+}
+
+/// Changes to node kinds outside of `allowed`, as errors located in the old text.
+pub fn guard(title: &str, old_text: &str, new_text: &str, allowed: &[&str]) -> Vec<Diagnostic> {
+    let index = LineIndex::new(old_text);
+    diff_masking(old_text, new_text, allowed)
+        .into_iter()
+        .filter(|c| !allowed.contains(&c.node_kind()))
+        .map(|c| {
+            let mut d = Diagnostic::new(
+                Severity::Error,
+                "diff/unexpected-change",
+                c.old_range().unwrap_or(0..0),
+                format!(
+                    "visitor only touches {:?}, but: {}",
+                    allowed,
+                    c.render().replace('\n', " ")
+                ),
+            );
+            d.locate(title, &index, old_text);
+            d
+        })
+        .collect()
+}
@@ -0,0 +1,303 @@
+use crate::prelude::*;
+use parse_wiki_text::{Node, TableCellType};
+use std::ops::Range;
+
+/// Logical grid over a `{| ... |}` wikitable, with `rowspan`/`colspan` resolved.
+///
+/// A cell spanning several slots shows up in each of them, so `cell(r, c)` is
+/// what a reader of the rendered table would see at row `r`, column `c`.
+pub struct TableGrid<'a> {
+    source: &'a str,
+    range: Range<usize>,
+    cells: Vec<GridCell>,
+    /// `slots[row][col]` is an index into `cells`
+    slots: Vec<Vec<Option<usize>>>,
+    /// Source range of every grid row, i.e. of the `|-` rows that had cells.
+    rows: Vec<Range<usize>>,
+    header_row: Option<usize>,
+    columns: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GridCell {
+    /// The whole cell, attributes included.
+    pub range: Range<usize>,
+    /// Just the content, without attributes.
+    pub content: Range<usize>,
+    pub attributes: Option<Range<usize>>,
+    /// Top-left slot of the cell.
+    pub row: usize,
+    pub col: usize,
+    pub rowspan: usize,
+    pub colspan: usize,
+    pub heading: bool,
+}
+
+/// `rowspan` or `colspan`.
+fn span_attribute(attributes: &str, name: &'static str) -> usize {
+    static ROWSPAN: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    static COLSPAN: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let cell = match name {
+        "rowspan" => &ROWSPAN,
+        _ => &COLSPAN,
+    };
+    let re = cell.get_or_init(|| {
+        regex::RegexBuilder::new(&format!(r#"\b{}\s*=\s*["']?\s*(\d+)"#, name))
+            .case_insensitive(true)
+            .build()
+            .unwrap()
+    });
+    re.captures(attributes)
+        .and_then(|c| c[1].parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(1)
+}
+
+impl<'a> TableGrid<'a> {
+    /// `None` if the node is not a table.
+    pub fn new(node: &Node, source: &'a str) -> Option<Self> {
+        let rows = match node {
+            Node::Table { rows, .. } => rows,
+            _ => return None,
+        };
+        let mut grid = TableGrid {
+            source,
+            range: node.range(),
+            cells: vec![],
+            slots: vec![],
+            rows: vec![],
+            header_row: None,
+            columns: vec![],
+        };
+        for row in rows.iter().filter(|r| !r.cells.is_empty()) {
+            let r = grid.rows.len();
+            grid.rows.push(row.start..row.end);
+            if grid.slots.len() <= r {
+                grid.slots.push(vec![]);
+            }
+            let mut col = 0;
+            for cell in &row.cells {
+                // skip slots taken by rowspans from above
+                while grid.slots[r].get(col).is_some_and(|s| s.is_some()) {
+                    col += 1;
+                }
+                let attributes = cell
+                    .attributes
+                    .as_ref()
+                    .filter(|a| !a.is_empty())
+                    .map(|a| a.first().unwrap().start()..a.last().unwrap().end());
+                let (rowspan, colspan) = match &attributes {
+                    Some(a) => (
+                        span_attribute(&source[a.clone()], "rowspan"),
+                        span_attribute(&source[a.clone()], "colspan"),
+                    ),
+                    None => (1, 1),
+                };
+                let content = if cell.content.is_empty() {
+                    cell.end..cell.end
+                } else {
+                    cell.content.first().unwrap().start()..cell.content.last().unwrap().end()
+                };
+                let index = grid.cells.len();
+                grid.cells.push(GridCell {
+                    range: cell.start..cell.end,
+                    content,
+                    attributes,
+                    row: r,
+                    col,
+                    rowspan,
+                    colspan,
+                    heading: cell.type_ == TableCellType::Heading,
+                });
+                for rr in r..r + rowspan {
+                    if grid.slots.len() <= rr {
+                        grid.slots.push(vec![]);
+                    }
+                    let slots = &mut grid.slots[rr];
+                    if slots.len() < col + colspan {
+                        slots.resize(col + colspan, None);
+                    }
+                    for slot in &mut slots[col..col + colspan] {
+                        slot.get_or_insert(index);
+                    }
+                }
+                col += colspan;
+            }
+        }
+        // rowspans running past the last row don't make new rows
+        grid.slots.truncate(grid.rows.len());
+
+        grid.header_row = (0..grid.rows.len()).find(|r| {
+            grid.slots[*r]
+                .iter()
+                .flatten()
+                .all(|c| grid.cells[*c].heading)
+        });
+        if let Some(h) = grid.header_row {
+            grid.columns = grid.slots[h]
+                .iter()
+                .map(|c| match c {
+                    Some(c) => grid.text(&grid.cells[*c]).trim().to_lowercase(),
+                    None => String::new(),
+                })
+                .collect();
+        }
+        Some(grid)
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Widest row, in slots.
+    pub fn column_count(&self) -> usize {
+        self.slots.iter().map(|r| r.len()).max().unwrap_or(0)
+    }
+
+    /// Source range of the row, from its `|-` to the next one.
+    pub fn row_range(&self, row: usize) -> Range<usize> {
+        self.rows[row].clone()
+    }
+
+    /// First row made of heading cells only, if any.
+    pub fn header_row(&self) -> Option<usize> {
+        self.header_row
+    }
+
+    /// Lowercased header captions, one per logical column.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Column whose header caption is `caption`, case-insensitive.
+    pub fn column(&self, caption: &str) -> Option<usize> {
+        let caption = caption.to_lowercase();
+        self.columns.iter().position(|c| c == &caption)
+    }
+
+    pub fn cell(&self, row: usize, col: usize) -> Option<&GridCell> {
+        let index = (*self.slots.get(row)?.get(col)?)?;
+        Some(&self.cells[index])
+    }
+
+    /// The cell at every slot of the row, spanned cells repeated.
+    pub fn row(&self, row: usize) -> Vec<Option<&GridCell>> {
+        self.slots[row]
+            .iter()
+            .map(|c| c.map(|c| &self.cells[c]))
+            .collect()
+    }
+
+    /// Every source cell once, in source order.
+    pub fn cells(&self) -> &[GridCell] {
+        &self.cells
+    }
+
+    pub fn text(&self, cell: &GridCell) -> &'a str {
+        &self.source[cell.content.clone()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    /// Text of every slot, `-` for empty ones.
+    fn texts(text: &str) -> Vec<Vec<String>> {
+        let parsed = parse::parse_text(text);
+        let grid = TableGrid::new(&parsed.nodes[0], text).unwrap();
+        (0..grid.row_count())
+            .map(|r| {
+                grid.row(r)
+                    .into_iter()
+                    .map(|c| c.map_or("-", |c| grid.text(c).trim()).to_string())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plain_table() {
+        let text = "{|\n! A !! B\n|-\n| 1 || 2\n|-\n| 3 || 4\n|}";
+        assert_eq!(
+            texts(text),
+            vec![vec!["A", "B"], vec!["1", "2"], vec!["3", "4"]]
+        );
+        let parsed = parse::parse_text(text);
+        let grid = TableGrid::new(&parsed.nodes[0], text).unwrap();
+        assert_eq!(grid.header_row(), Some(0));
+        assert_eq!(grid.columns(), ["a", "b"]);
+        assert_eq!(grid.column("B"), Some(1));
+        assert_eq!(grid.cells().len(), 6);
+    }
+
+    #[test]
+    fn colspan_fills_the_slots_to_the_right() {
+        let text = "{|\n! A !! B !! C\n|-\n| colspan=\"2\" | 1 || 2\n|-\n| 3 || colspan=2 | 4\n|}";
+        assert_eq!(
+            texts(text),
+            vec![
+                vec!["A", "B", "C"],
+                vec!["1", "1", "2"],
+                vec!["3", "4", "4"]
+            ]
+        );
+        let parsed = parse::parse_text(text);
+        let grid = TableGrid::new(&parsed.nodes[0], text).unwrap();
+        let cell = grid.cell(1, 1).unwrap();
+        assert_eq!((cell.row, cell.col, cell.colspan), (1, 0, 2));
+    }
+
+    #[test]
+    fn rowspan_pushes_the_cells_below_aside() {
+        let text =
+            "{|\n! A !! B !! C\n|-\n| rowspan=2 | 1 || 2 || 3\n|-\n| 4 || 5\n|-\n| 6 || 7 || 8\n|}";
+        assert_eq!(
+            texts(text),
+            vec![
+                vec!["A", "B", "C"],
+                vec!["1", "2", "3"],
+                vec!["1", "4", "5"],
+                vec!["6", "7", "8"],
+            ]
+        );
+    }
+
+    #[test]
+    fn both_spans_and_short_rows() {
+        let text = "{|\n| rowspan='2' colspan='2' | 1 || 2\n|-\n| 3\n|-\n| 4\n|}";
+        assert_eq!(
+            texts(text),
+            vec![vec!["1", "1", "2"], vec!["1", "1", "3"], vec!["4"]]
+        );
+    }
+
+    #[test]
+    fn rowspan_past_the_end_adds_no_rows() {
+        let text = "{|\n| rowspan=5 | 1 || 2\n|-\n| 3\n|}";
+        assert_eq!(texts(text), vec![vec!["1", "2"], vec!["1", "3"]]);
+    }
+
+    #[test]
+    fn no_header_without_a_heading_row() {
+        let text = "{|\n| 1 || 2\n|}";
+        let parsed = parse::parse_text(text);
+        let grid = TableGrid::new(&parsed.nodes[0], text).unwrap();
+        assert_eq!(grid.header_row(), None);
+        assert!(grid.columns().is_empty());
+    }
+
+    #[test]
+    fn span_attributes() {
+        assert_eq!(span_attribute("colspan=\"3\"", "colspan"), 3);
+        assert_eq!(span_attribute("style=\"x\" ROWSPAN = 2", "rowspan"), 2);
+        assert_eq!(span_attribute("colspan=0", "colspan"), 1);
+        assert_eq!(span_attribute("colspan=x", "colspan"), 1);
+        assert_eq!(span_attribute("rowspan=2", "colspan"), 1);
+    }
+}
@@ -0,0 +1,432 @@
+use crate::prelude::*;
+use parse_wiki_text::Node;
+use std::borrow::Cow;
+use std::ops::Range;
+
+/// Editable view of a `{{template|...}}` call.
+///
+/// Edits are recorded against the source text and turned into the smallest
+/// replacements that do the job, so whatever whitespace and line layout the
+/// author used around untouched parts stays as is.
+pub struct TemplateView<'a> {
+    source: &'a str,
+    range: Range<usize>,
+    name_range: Range<usize>,
+    new_name: Option<String>,
+    params: Vec<Param<'a>>,
+}
+
+struct Param<'a> {
+    /// `None` for parameters added through the view
+    slot: Option<Slot>,
+    name: Option<Cow<'a, str>>,
+    value: Cow<'a, str>,
+    removed: bool,
+}
+
+/// Where an existing parameter sits in the source.
+struct Slot {
+    /// From the `|` up to (not including) the next `|` or the closing `}}`.
+    range: Range<usize>,
+    name: Option<Range<usize>>,
+    value: Range<usize>,
+}
+
+/// Whitespace around a `|name = value` parameter.
+#[derive(Default, Clone)]
+struct Layout<'a> {
+    lead: &'a str,
+    eq_pre: &'a str,
+    eq_post: &'a str,
+    trail: &'a str,
+}
+
+impl<'a> TemplateView<'a> {
+    /// `None` if the node is not a template.
+    pub fn new(node: &Node, source: &'a str) -> Option<Self> {
+        let (name, parameters, start, end) = match node {
+            Node::Template {
+                name,
+                parameters,
+                start,
+                end,
+            } => (name, parameters, *start, *end),
+            _ => return None,
+        };
+        let name_range = if name.is_empty() {
+            start + 2..start + 2
+        } else {
+            name.first().unwrap().start()..name.last().unwrap().end()
+        };
+        let close = end - 2; // `}}`
+
+        let pipes = parameters
+            .iter()
+            .map(|p| source[..p.start].rfind('|').unwrap())
+            .collect::<Vec<_>>();
+        let params = parameters
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let slot_end = pipes.get(i + 1).copied().unwrap_or(close);
+                let name = p.name.as_ref().map(|_| p.name_str(source));
+                let name_range = p
+                    .name
+                    .as_ref()
+                    .map(|n| n.first().unwrap().start()..n.last().unwrap().end());
+                let value = if p.value.is_empty() {
+                    p.end..p.end
+                } else {
+                    p.value.first().unwrap().start()..p.value.last().unwrap().end()
+                };
+                Param {
+                    slot: Some(Slot {
+                        range: pipes[i]..slot_end,
+                        name: name_range,
+                        value,
+                    }),
+                    name: name.map(Cow::Borrowed),
+                    value: Cow::Borrowed(p.val_str(source)),
+                    removed: false,
+                }
+            })
+            .collect();
+        Some(Self {
+            source,
+            range: start..end,
+            name_range,
+            new_name: None,
+            params,
+        })
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// Source text of the whole call, edits not included.
+    pub fn as_str(&self) -> &'a str {
+        &self.source[self.range.clone()]
+    }
+
+    pub fn name(&self) -> &str {
+        match &self.new_name {
+            Some(n) => n,
+            None => &self.source[self.name_range.clone()],
+        }
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.new_name = Some(name.to_string());
+    }
+
+    /// Number of parameters, named and positional.
+    pub fn len(&self) -> usize {
+        self.params.iter().filter(|p| !p.removed).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn positional(&self) -> Vec<&str> {
+        self.params
+            .iter()
+            .filter(|p| !p.removed && p.name.is_none())
+            .map(|p| p.value.as_ref())
+            .collect()
+    }
+
+    pub fn named(&self) -> Vec<(&str, &str)> {
+        self.params
+            .iter()
+            .filter(|p| !p.removed)
+            .filter_map(|p| Some((p.name.as_deref()?, p.value.as_ref())))
+            .collect()
+    }
+
+    /// `key` is either a parameter name or a 1-based position.
+    fn find(&self, key: &str) -> Option<usize> {
+        let named = self
+            .params
+            .iter()
+            .position(|p| !p.removed && p.name.as_deref() == Some(key));
+        if named.is_some() {
+            return named;
+        }
+        let n: usize = key.parse().ok()?;
+        self.params
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| !p.removed && p.name.is_none())
+            .nth(n.checked_sub(1)?)
+            .map(|(i, _)| i)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.find(key).map(|i| self.params[i].value.as_ref())
+    }
+
+    /// Sets the value, appending a new named parameter if there's no such one yet.
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.find(key) {
+            Some(i) => self.params[i].value = Cow::Owned(value.to_string()),
+            None => self.insert(key, value),
+        }
+    }
+
+    /// Returns false if there's no such parameter.
+    pub fn rename(&mut self, key: &str, new_name: &str) -> bool {
+        match self.find(key) {
+            Some(i) => {
+                self.params[i].name = Some(Cow::Owned(new_name.to_string()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Appends a named parameter.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.params.push(Param {
+            slot: None,
+            name: Some(Cow::Owned(name.to_string())),
+            value: Cow::Owned(value.to_string()),
+            removed: false,
+        });
+    }
+
+    /// Returns false if there's no `anchor` parameter.
+    pub fn insert_after(&mut self, anchor: &str, name: &str, value: &str) -> bool {
+        let i = match self.find(anchor) {
+            Some(i) => i,
+            None => return false,
+        };
+        // keep the order of several inserts after the same anchor
+        let mut at = i + 1;
+        while at < self.params.len() && self.params[at].slot.is_none() {
+            at += 1;
+        }
+        self.params.insert(
+            at,
+            Param {
+                slot: None,
+                name: Some(Cow::Owned(name.to_string())),
+                value: Cow::Owned(value.to_string()),
+                removed: false,
+            },
+        );
+        true
+    }
+
+    /// Returns false if there's no such parameter.
+    pub fn remove(&mut self, key: &str) -> bool {
+        match self.find(key) {
+            Some(i) => {
+                if self.params[i].slot.is_some() {
+                    self.params[i].removed = true;
+                } else {
+                    self.params.remove(i);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn layout_of(&self, slot: &Slot) -> Layout<'a> {
+        let s = self.source;
+        let name = match &slot.name {
+            Some(n) => n,
+            None => return Layout::default(),
+        };
+        let eq = name.end + s[name.end..].find('=').unwrap();
+        // an empty value leaves us guessing; count it all as trailing
+        let value_start = slot.value.start.max(eq + 1);
+        Layout {
+            lead: &s[slot.range.start + 1..name.start],
+            eq_pre: &s[name.end..eq],
+            eq_post: &s[eq + 1..value_start],
+            trail: &s[slot.value.end.max(value_start)..slot.range.end],
+        }
+    }
+
+    /// Layout of the closest existing named parameter before `index`, or failing that, any.
+    fn layout_near(&self, index: usize) -> Layout<'a> {
+        fn named_slot<'p>(p: &'p Param) -> Option<&'p Slot> {
+            p.slot
+                .as_ref()
+                .filter(|s| s.name.is_some() && !s.value.is_empty())
+        }
+        self.params[..index]
+            .iter()
+            .rev()
+            .chain(self.params[index..].iter())
+            .find_map(named_slot)
+            .map(|s| self.layout_of(s))
+            .unwrap_or_default()
+    }
+
+    /// Replacements against the source text, ready for `take_replacements`.
+    pub fn replacements(&self) -> Vec<(String, Range<usize>)> {
+        let mut out = vec![];
+        if let Some(n) = &self.new_name {
+            out.push((n.clone(), self.name_range.clone()));
+        }
+        // where inserted params go: right after the previous existing parameter
+        let mut insert_at = self.range.end - 2;
+        let mut pending = String::new();
+        let mut pending_at = None;
+        for (i, p) in self.params.iter().enumerate() {
+            let slot = match &p.slot {
+                Some(slot) => slot,
+                None => {
+                    let layout = self.layout_near(i);
+                    pending += "|";
+                    if let Some(name) = &p.name {
+                        pending += layout.lead;
+                        pending += name;
+                        pending += layout.eq_pre;
+                        pending += "=";
+                        pending += layout.eq_post;
+                    }
+                    pending += &p.value;
+                    pending += layout.trail;
+                    pending_at.get_or_insert(insert_at);
+                    continue;
+                }
+            };
+            if let Some(at) = pending_at.take() {
+                out.push((std::mem::take(&mut pending), at..at));
+            }
+            insert_at = slot.range.end;
+            if p.removed {
+                out.push((String::new(), slot.range.clone()));
+                continue;
+            }
+            match (&p.name, &slot.name) {
+                (Some(name), Some(name_range)) if name != &self.source[name_range.clone()] => {
+                    out.push((name.to_string(), name_range.clone()));
+                }
+                (Some(name), None) => {
+                    // positional turned named
+                    let layout = self.layout_near(i);
+                    let prefix = format!("{}{}={}", name, layout.eq_pre, layout.eq_post);
+                    out.push((prefix, slot.value.start..slot.value.start));
+                }
+                _ => {}
+            }
+            if p.value != self.source[slot.value.clone()] {
+                let mut value = p.value.to_string();
+                if slot.value.is_empty() && slot.name.is_some() {
+                    value.insert_str(0, self.layout_near(i).eq_post);
+                }
+                out.push((value, slot.value.clone()));
+            }
+        }
+        if let Some(at) = pending_at {
+            out.push((pending, at..at));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    /// Runs `edit` on the view of the first template in `text` and applies the result.
+    fn edited(text: &str, edit: impl FnOnce(&mut TemplateView)) -> String {
+        let parsed = parse::parse_text(text);
+        let node = parsed
+            .nodes
+            .iter()
+            .find(|n| matches!(n, Node::Template { .. }))
+            .unwrap();
+        let mut view = TemplateView::new(node, text).unwrap();
+        edit(&mut view);
+        let mut replacements = view.replacements();
+        replacements.sort_by_key(|(_, r)| r.start);
+        parse::apply_replacements(text, &replacements)
+    }
+
+    #[test]
+    fn reads_parameters() {
+        let text = "{{Move| 5K |name = Kick|2=x}}";
+        let parsed = parse::parse_text(text);
+        let view = TemplateView::new(&parsed.nodes[0], text).unwrap();
+        assert_eq!(view.name(), "Move");
+        assert_eq!(view.len(), 3);
+        assert_eq!(view.get("1"), Some("5K"));
+        assert_eq!(view.get("name"), Some("Kick"));
+        assert_eq!(view.get("2"), Some("x"));
+        assert_eq!(view.get("3"), None);
+        assert_eq!(view.named(), vec![("name", "Kick"), ("2", "x")]);
+    }
+
+    #[test]
+    fn untouched_view_changes_nothing() {
+        let text = "{{Move\n| a = 1\n| b = 2\n}}";
+        assert_eq!(edited(text, |_| {}), text);
+    }
+
+    #[test]
+    fn set_keeps_the_layout() {
+        let text = "{{Move\n| a = 1\n| b = 2\n}}";
+        let out = edited(text, |v| v.set("a", "one"));
+        assert_eq!(out, "{{Move\n| a = one\n| b = 2\n}}");
+    }
+
+    #[test]
+    fn insert_copies_the_layout_of_its_neighbours() {
+        let text = "{{Move\n| a = 1\n| b = 2\n}}";
+        let out = edited(text, |v| v.insert("c", "3"));
+        assert_eq!(out, "{{Move\n| a = 1\n| b = 2\n| c = 3\n}}");
+        let out = edited(text, |v| {
+            assert!(v.insert_after("a", "x", "9"));
+            assert!(v.insert_after("a", "y", "8"));
+        });
+        assert_eq!(out, "{{Move\n| a = 1\n| x = 9\n| y = 8\n| b = 2\n}}");
+    }
+
+    #[test]
+    fn remove_takes_its_pipe_along() {
+        let text = "{{Move|a=1|b=2|c=3}}";
+        assert_eq!(edited(text, |v| assert!(v.remove("b"))), "{{Move|a=1|c=3}}");
+        assert_eq!(edited(text, |v| assert!(v.remove("c"))), "{{Move|a=1|b=2}}");
+        assert_eq!(edited(text, |v| assert!(!v.remove("d"))), text);
+    }
+
+    #[test]
+    fn pipes_inside_values_are_not_parameters() {
+        let text = "{{Move|[[GGST/Sol|Sol]]|note={{clr|1|5K}}|c=3}}";
+        let out = edited(text, |v| {
+            assert_eq!(v.len(), 3);
+            assert_eq!(v.get("1"), Some("[[GGST/Sol|Sol]]"));
+            assert!(v.remove("note"));
+        });
+        assert_eq!(out, "{{Move|[[GGST/Sol|Sol]]|c=3}}");
+        let out = edited(text, |v| assert!(v.remove("1")));
+        assert_eq!(out, "{{Move|note={{clr|1|5K}}|c=3}}");
+    }
+
+    #[test]
+    fn rename_and_set_name() {
+        let text = "{{Move|a=1|x}}";
+        let out = edited(text, |v| {
+            v.set_name("Move2");
+            assert!(v.rename("a", "b"));
+            assert!(v.rename("1", "c"));
+        });
+        assert_eq!(out, "{{Move2|b=1|c=x}}");
+    }
+
+    #[test]
+    fn set_fills_an_empty_value() {
+        // what follows an empty value's `=` is kept as its trailing whitespace
+        let text = "{{Move\n| a =\n| b = 2\n}}";
+        let out = edited(text, |v| v.set("a", "1"));
+        assert_eq!(out, "{{Move\n| a = 1\n| b = 2\n}}");
+    }
+}
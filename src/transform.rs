@@ -0,0 +1,191 @@
+use crate::parse::WikiVisitor;
+use crate::visitors;
+use crate::Runner;
+use anyhow::Context;
+use std::marker::PhantomData;
+
+/// Something that can be run as `--mode <NAME>`: a per-run factory of visitors.
+///
+/// It loads its configs, compiles its regexes and so on once, and every page's
+/// visitor borrows from it. The shared runner takes care of picking pages,
+/// transforming them, dumping the results to `out/<NAME>/` and, with `--apply`,
+/// editing.
+pub trait Transform: Sized + 'static {
+    const NAME: &'static str;
+    /// Edit summary, unless the job says otherwise.
+    const SUMMARY: &'static str;
+    type Visitor<'a>: WikiVisitor;
+    /// What the `--config` file holds, `()` for transforms that take none.
+    type Config: serde::de::DeserializeOwned;
+
+    /// `config` is the `--config` file, read by the registry, if one was given.
+    fn load(config: Option<Self::Config>) -> anyhow::Result<Self>;
+
+    fn job(&self) -> Job {
+        Job::default()
+    }
+
+    /// A fresh visitor for one page.
+    fn visitor<'a>(&'a self, title: &'a str, text: &'a str) -> anyhow::Result<Self::Visitor<'a>>;
+}
+
+pub fn read_json5<T: serde::de::DeserializeOwned>(path: &str) -> anyhow::Result<T> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path))?;
+    json5::from_str(&text).with_context(|| format!("parsing {}", path))
+}
+
+/// Pages to go over: a list, or everything under a prefix.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum PagesSpec {
+    List(Vec<String>),
+    Spec {
+        prefix: String,
+        /// Regex the titles must match.
+        #[serde(default)]
+        pattern: Option<String>,
+        #[serde(default)]
+        skip: Vec<String>,
+    },
+}
+
+impl PagesSpec {
+    pub async fn resolve(&self, api: &mediawiki::api::Api) -> anyhow::Result<Vec<String>> {
+        match self {
+            PagesSpec::List(pages) => Ok(pages.clone()),
+            PagesSpec::Spec {
+                prefix,
+                pattern,
+                skip,
+            } => {
+                let re = pattern.as_deref().map(regex::Regex::new).transpose()?;
+                let mut pages = crate::api::all_pages_with_prefix(api, prefix).await;
+                pages.retain(|p| !skip.contains(p) && re.as_ref().is_none_or(|re| re.is_match(p)));
+                Ok(pages)
+            }
+        }
+    }
+
+    /// Whether `title` is one of the pages, for when they come from elsewhere.
+    pub fn matches(&self, title: &str) -> anyhow::Result<bool> {
+        match self {
+            PagesSpec::List(pages) => Ok(pages.iter().any(|p| p == title)),
+            PagesSpec::Spec {
+                prefix,
+                pattern,
+                skip,
+            } => {
+                let re = pattern.as_deref().map(regex::Regex::new).transpose()?;
+                Ok(title.starts_with(prefix.as_str())
+                    && !skip.iter().any(|p| p == title)
+                    && re.is_none_or(|re| re.is_match(title)))
+            }
+        }
+    }
+}
+
+/// Per-config defaults for a run.
+#[derive(Default)]
+pub struct Job {
+    /// Used when neither `--page` nor `--prefix` is given.
+    pub pages: Option<PagesSpec>,
+    /// Overrides `Transform::SUMMARY`.
+    pub summary: Option<String>,
+    pub minor: bool,
+    /// Why `--apply` is refused, if it is.
+    pub refuse_apply: Option<String>,
+}
+
+/// The editing half of a job file, for transforms configured by one.
+#[derive(serde::Deserialize)]
+pub struct EditSettings {
+    #[serde(default)]
+    apply: bool,
+    #[serde(default)]
+    comment: String,
+    isminor: Option<bool>,
+}
+
+impl EditSettings {
+    pub fn job(&self, pages: &PagesSpec) -> Job {
+        let refuse_apply = if !self.apply {
+            Some("noaply in config")
+        } else if self.comment.is_empty() {
+            Some("no comment in config")
+        } else if self.isminor.is_none() {
+            Some("no isminor in config")
+        } else {
+            None
+        };
+        Job {
+            pages: Some(pages.clone()),
+            summary: Some(self.comment.clone()),
+            minor: self.isminor.unwrap_or(false),
+            refuse_apply: refuse_apply.map(str::to_string),
+        }
+    }
+}
+
+/// A transform with its config loaded.
+pub trait Prepared {
+    fn name(&self) -> &'static str;
+    fn job(&self) -> Job;
+    fn summary(&self) -> String;
+    fn transform(&self, title: &str, text: &str, runner: &mut Runner) -> anyhow::Result<String>;
+}
+
+impl<T: Transform> Prepared for T {
+    fn name(&self) -> &'static str {
+        T::NAME
+    }
+    fn job(&self) -> Job {
+        Transform::job(self)
+    }
+    fn summary(&self) -> String {
+        Transform::job(self)
+            .summary
+            .unwrap_or_else(|| T::SUMMARY.to_string())
+    }
+    fn transform(&self, title: &str, text: &str, runner: &mut Runner) -> anyhow::Result<String> {
+        runner.transform(self, title, text)
+    }
+}
+
+/// Type-erased `Transform`, for the registry.
+pub trait Entry {
+    fn name(&self) -> &'static str;
+    fn prepare(&self, config: Option<&str>) -> anyhow::Result<Box<dyn Prepared>>;
+}
+
+struct Registered<T>(PhantomData<T>);
+
+impl<T: Transform + 'static> Entry for Registered<T> {
+    fn name(&self) -> &'static str {
+        T::NAME
+    }
+    fn prepare(&self, config: Option<&str>) -> anyhow::Result<Box<dyn Prepared>> {
+        let config = config.map(read_json5::<T::Config>).transpose()?;
+        Ok(Box::new(T::load(config)?))
+    }
+}
+
+fn entry<T: Transform + 'static>() -> Box<dyn Entry> {
+    Box::new(Registered::<T>(PhantomData))
+}
+
+/// Every transform `--mode` knows about.
+pub fn registry() -> Vec<Box<dyn Entry>> {
+    vec![
+        entry::<visitors::ColorTransform>(),
+        entry::<visitors::ComboTableTransform>(),
+        entry::<visitors::movecard::MoveCardTransform>(),
+        entry::<visitors::FindReplaceTransform>(),
+        entry::<visitors::RelinkTransform>(),
+        entry::<visitors::ScriptTransform>(),
+        entry::<visitors::UrlTransform>(),
+    ]
+}
+
+pub fn find(name: &str) -> Option<Box<dyn Entry>> {
+    registry().into_iter().find(|e| e.name() == name)
+}
@@ -0,0 +1,195 @@
+use crate::diagnostics::{Diagnostic, LineIndex, Severity};
+use crate::parse::{self, WikiVisitor};
+use crate::prelude::*;
+use crate::semantic_diff;
+use crate::transform::Transform;
+use crate::warning_policy::WarningPolicy;
+use parse_wiki_text::Node;
+use std::ops::Range;
+
+/// `parse::transform_text`, but the result is re-parsed and checked before it's returned:
+/// - every node that doesn't overlap a replaced span must still be there, shifted;
+/// - running a fresh visitor over the output must not want any further changes;
+/// - if the visitor declares what it `touches`, nothing else may change structurally.
+pub fn verified_transform<T: Transform>(
+    transform: &T,
+    title: &str,
+    existing_text: &str,
+    policy: &WarningPolicy,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> anyhow::Result<String> {
+    let replacements = parse::compute_replacements(
+        title,
+        existing_text,
+        &mut transform.visitor(title, existing_text)?,
+        policy,
+        diagnostics,
+    )?;
+    let new_text = parse::apply_replacements(existing_text, &replacements);
+    if new_text == existing_text {
+        return Ok(new_text);
+    }
+
+    let mut problems = vec![];
+    for node in changed_nodes(existing_text, &new_text, &replacements) {
+        problems.push(locate(
+            Diagnostic::new(
+                Severity::Error,
+                "verify/ast-changed",
+                node.1.clone(),
+                format!(
+                    "{} outside of the replaced spans did not survive the edit",
+                    node.0
+                ),
+            ),
+            title,
+            existing_text,
+        ));
+    }
+
+    if let Some(allowed) = transform.visitor(title, existing_text)?.touches() {
+        problems.extend(semantic_diff::guard(
+            title,
+            existing_text,
+            &new_text,
+            allowed,
+        ));
+    }
+
+    // rerun diagnostics would only repeat the first run's
+    let mut rerun_diagnostics = vec![];
+    let rerun = transform.visitor(title, &new_text).and_then(|mut visitor| {
+        parse::compute_replacements(
+            title,
+            &new_text,
+            &mut visitor,
+            policy,
+            &mut rerun_diagnostics,
+        )
+    });
+    match rerun {
+        Ok(second) => {
+            let second = second
+                .into_iter()
+                .filter(|(rep, range)| rep != &new_text[range.clone()])
+                .collect::<Vec<_>>();
+            if let Some((rep, range)) = second.first() {
+                problems.push(locate(
+                    Diagnostic::new(
+                        Severity::Error,
+                        "verify/not-idempotent",
+                        range.clone(),
+                        format!(
+                            "second run still wants {} change(s), first: {:?} -> {:?}",
+                            second.len(),
+                            &new_text[range.clone()],
+                            rep
+                        ),
+                    ),
+                    title,
+                    &new_text,
+                ));
+            }
+        }
+        Err(e) => problems.push(locate(
+            Diagnostic::new(
+                Severity::Error,
+                "verify/rerun-failed",
+                0..0,
+                format!("second run over the output failed: {}", e),
+            ),
+            title,
+            &new_text,
+        )),
+    }
+
+    let failed = problems.len();
+    diagnostics.extend(problems);
+    if failed > 0 {
+        anyhow::bail!("verification failed with {} problem(s)", failed);
+    }
+    Ok(new_text)
+}
+
+fn locate(mut d: Diagnostic, title: &str, text: &str) -> Diagnostic {
+    d.locate(title, &LineIndex::new(text), text);
+    d
+}
+
+pub(crate) fn flatten<'n>(nodes: &'n [Node<'n>], out: &mut Vec<&'n Node<'n>>) {
+    for n in nodes {
+        out.push(n);
+        flatten_children(n, out);
+    }
+}
+fn flatten_children<'n>(node: &'n Node<'n>, out: &mut Vec<&'n Node<'n>>) {
+    for c in node.children() {
+        out.push(c);
+        flatten_children(c, out);
+    }
+}
+
+/// Where `pos` in the old text ends up in the new one. `None` if it's inside a replaced span.
+/// Text inserted right at `pos` goes after a node ending there but before one starting there.
+fn map_position(
+    pos: usize,
+    is_end: bool,
+    replacements: &[(String, Range<usize>)],
+) -> Option<usize> {
+    let mut shift: isize = 0;
+    for (rep, range) in replacements {
+        if range.start < pos && pos < range.end {
+            return None;
+        }
+        let before = if range.is_empty() {
+            range.start < pos || (range.start == pos && !is_end)
+        } else {
+            range.end <= pos
+        };
+        if before {
+            shift += rep.len() as isize - range.len() as isize;
+        }
+    }
+    Some((pos as isize + shift) as usize)
+}
+
+/// Nodes of the old text (kind, old range) that are missing from the new text's tree.
+fn changed_nodes(
+    old_text: &str,
+    new_text: &str,
+    replacements: &[(String, Range<usize>)],
+) -> Vec<(&'static str, Range<usize>)> {
+    let old = parse::parse_text(old_text);
+    let new = parse::parse_text(new_text);
+    let mut old_nodes = vec![];
+    flatten(&old.nodes, &mut old_nodes);
+    let mut new_nodes = vec![];
+    flatten(&new.nodes, &mut new_nodes);
+    let new_set = new_nodes
+        .iter()
+        .map(|n| (n.kind(), n.start(), n.end()))
+        .collect::<std::collections::HashSet<_>>();
+
+    let mut out = vec![];
+    for node in old_nodes {
+        // text merges and splits around any edit, it proves nothing
+        if matches!(node, Node::Text { .. }) {
+            continue;
+        }
+        let range = node.range();
+        if replacements.iter().any(|(_, r)| r == &range) {
+            continue; // replaced wholesale
+        }
+        let (start, end) = match (
+            map_position(range.start, false, replacements),
+            map_position(range.end, true, replacements),
+        ) {
+            (Some(s), Some(e)) => (s, e),
+            _ => continue, // partially replaced
+        };
+        if !new_set.contains(&(node.kind(), start, end)) {
+            out.push((node.kind(), range));
+        }
+    }
+    out
+}
@@ -1,27 +1,38 @@
 // use crate::parse;
+use crate::diagnostics::{Diagnostic, DiagnosticSink};
 use crate::parse::WikiVisitor;
 use crate::prelude::*;
-use parse_wiki_text::Node;
+use crate::selector::{Element, Selector};
+use crate::template_view::TemplateView;
+use crate::transform::{read_json5, Job, PagesSpec, Transform};
+use anyhow::Context;
 use std::collections::{BTreeMap, BTreeSet};
 
-pub struct ColorVisitor {
-    config: ColorConfig,
-    base_text: String,
+pub struct ColorVisitor<'a> {
+    prepared: &'a PreparedColor,
+    selector: &'a Selector,
+    base_text: &'a str,
     replacements: Vec<(String, std::ops::Range<usize>)>,
-    errors: bool,
-    seen: std::collections::BTreeMap<String, std::collections::BTreeSet<String>>,
-    regex_cache: BTreeMap<String, regex::Regex>,
+    diagnostics: DiagnosticSink,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Clone)]
 pub struct ColorConfig {
     moves: std::collections::BTreeMap<String, Vec<String>>,
     nonmoves: Vec<String>,
     // ok_colors: std::collections::BTreeMap<String, Vec<String>>,
     skip: BTreeSet<String>,
 }
-impl ColorVisitor {
-    pub fn new(mut config: ColorConfig) -> Self {
+
+/// A `ColorConfig` lowercased, with the move regex of every color compiled.
+pub struct PreparedColor {
+    config: ColorConfig,
+    regexes: BTreeMap<&'static str, regex::Regex>,
+}
+
+impl PreparedColor {
+    fn load(path: &str) -> anyhow::Result<Self> {
+        let mut config: ColorConfig = read_json5(path)?;
         for moves in config.moves.values_mut() {
             moves.iter_mut().for_each(|m| *m = m.to_lowercase());
         }
@@ -30,24 +41,96 @@ impl ColorVisitor {
             .nonmoves
             .iter_mut()
             .for_each(|m| *m = m.to_lowercase());
-        ColorVisitor {
-            config,
-            base_text: Default::default(),
-            replacements: Default::default(),
-            errors: false,
-            seen: Default::default(),
-            regex_cache: Default::default(),
-        }
+        let regexes = ["P", "K", "S", "H", "D"]
+            .into_iter()
+            .map(|c| (c, make_regex(c)))
+            .collect();
+        Ok(Self { config, regexes })
     }
+}
 
-    fn get_regex(&self, letter_color: &str) -> &regex::Regex {
-        self.regex_cache.get(letter_color).unwrap()
+/// Color configs for both games, picked by page prefix. Either may be missing.
+pub struct ColorTransform {
+    ggst: Option<PreparedColor>,
+    ggacr: Option<PreparedColor>,
+    selector: Selector,
+    /// From `SKIP_PAGES`.
+    skip_pages: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct SkipConfig {
+    skip_pages: Vec<String>,
+}
+
+const GGST_CONFIG: &str = "data/color/ggst.json5";
+const GGACR_CONFIG: &str = "data/color/ggacr.json5";
+/// Optional.
+const SKIP_PAGES: &str = "data/skip_pages.json5";
+
+impl ColorTransform {
+    /// Whether there's a color config for the page's game.
+    pub fn handles(&self, title: &str) -> bool {
+        (title.starts_with("GGST/") && self.ggst.is_some())
+            || (title.starts_with("GGACR/") && self.ggacr.is_some())
     }
 }
-fn make_regex(regex_cache: &mut BTreeMap<String, regex::Regex>, letter_color: &str) {
-    if regex_cache.contains_key(letter_color) {
-        return;
+
+fn load_if_present(path: &str) -> anyhow::Result<Option<PreparedColor>> {
+    if !std::path::Path::new(path).exists() {
+        return Ok(None);
     }
+    PreparedColor::load(path).map(Some)
+}
+
+impl Transform for ColorTransform {
+    const NAME: &'static str = "color";
+    const SUMMARY: &'static str = "Switch clr usage from numbers to letters";
+    type Visitor<'a> = ColorVisitor<'a>;
+    type Config = ();
+
+    fn load(_config: Option<()>) -> anyhow::Result<Self> {
+        Ok(Self {
+            ggst: load_if_present(GGST_CONFIG)?,
+            ggacr: load_if_present(GGACR_CONFIG)?,
+            selector: Selector::parse("template[name=clr], template[name=color]").unwrap(),
+            skip_pages: match std::path::Path::new(SKIP_PAGES).exists() {
+                true => read_json5::<SkipConfig>(SKIP_PAGES)?.skip_pages,
+                false => vec![],
+            },
+        })
+    }
+    fn job(&self) -> Job {
+        Job {
+            pages: Some(PagesSpec::Spec {
+                prefix: "GGACR/".to_string(),
+                pattern: None,
+                skip: self.skip_pages.clone(),
+            }),
+            refuse_apply: Some("color mode only reports unknown moves for now".to_string()),
+            ..Default::default()
+        }
+    }
+    fn visitor<'a>(&'a self, title: &'a str, text: &'a str) -> anyhow::Result<ColorVisitor<'a>> {
+        let (prepared, path) = match title {
+            t if t.starts_with("GGST/") => (&self.ggst, GGST_CONFIG),
+            t if t.starts_with("GGACR/") => (&self.ggacr, GGACR_CONFIG),
+            _ => anyhow::bail!("no color config for {:?}", title),
+        };
+        let prepared = prepared
+            .as_ref()
+            .with_context(|| format!("{} is missing", path))?;
+        Ok(ColorVisitor {
+            prepared,
+            selector: &self.selector,
+            base_text: text,
+            replacements: Default::default(),
+            diagnostics: Default::default(),
+        })
+    }
+}
+
+fn make_regex(letter_color: &str) -> regex::Regex {
     let prefixes = [
         r"s?j\d?.?", // super? jump in direction?
         r"\d+",
@@ -99,98 +182,85 @@ fn make_regex(regex_cache: &mut BTreeMap<String, regex::Regex>, letter_color: &s
         letter_pat.join(r"\s*|\s*"),
         suffixes.join(r"\s*|\s*")
     );
-    let re = regex::RegexBuilder::new(&pattern)
+    regex::RegexBuilder::new(&pattern)
         .case_insensitive(true)
         .build()
-        .unwrap();
-    regex_cache.insert(letter_color.to_string(), re);
+        .unwrap()
 }
 
-impl WikiVisitor for ColorVisitor {
-    fn set_base_text(&mut self, base_text: &str) {
-        self.base_text = base_text.to_string();
+impl WikiVisitor for ColorVisitor<'_> {
+    fn take_replacements(&mut self) -> Vec<(String, std::ops::Range<usize>)> {
+        std::mem::take(&mut self.replacements)
     }
-    fn get_replacements(&self) -> anyhow::Result<&[(String, std::ops::Range<usize>)]> {
-        if self.errors {
-            return Err(anyhow::anyhow!("errors encounterd"));
-        }
-        return Ok(self.replacements.as_slice());
+    fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.diagnostics.take()
+    }
+    fn touches(&self) -> Option<&'static [&'static str]> {
+        Some(&["Template"])
     }
-    fn visit_template(&mut self, node: &parse_wiki_text::Node) {
-        match node {
-            Node::Template {
-                name, parameters, ..
-            } => {
-                let name_text =
-                    &self.base_text[name.first().unwrap().start()..name.last().unwrap().end()];
-                match name_text {
-                    "clr" | "color" => {
-                        let set_color = parameters[0].as_str(&self.base_text);
-                        let letter_color = match set_color {
-                            "1" | "P" => "P",
-                            "2" | "K" => "K",
-                            "3" | "S" => "S",
-                            "4" | "H" => "H",
-                            "5" | "D" => "D",
-                            "6" | "7" | "8" => return, //leave it alone
-                            "added" | "new" | "removed" | "reworked" | "buff" | "nerf" => return, // leave alone
-                            "green" | "purple" => return, // ditto
-                            _ => panic!("unknown color {:?}", set_color),
-                        };
-                        if ["P", "K", "S", "H", "D"].contains(&set_color) {
-                            return;
-                        }
-                        if parameters.len() != 2 {
-                            self.errors = true;
-                            println!(
-                                "Not a valid color usage: {:?}",
-                                node.as_str(&self.base_text)
-                            )
-                        }
-                        let colored_text_orig = parameters[1].as_str(&self.base_text);
-                        let colored_text = colored_text_orig.to_lowercase();
-                        if self.config.nonmoves.iter().any(|m| m == &colored_text) {
-                            return;
-                        }
-                        if self.config.moves[letter_color]
-                            .iter()
-                            .any(|m| m == &colored_text)
-                        {
-                            // ok
-                        } else if self.config.skip.contains(colored_text.as_str()) {
-                            // ok for now
-                        } else {
-                            make_regex(&mut self.regex_cache, &letter_color);
-                            let re = self.get_regex(&letter_color);
-                            if !re.is_match(&colored_text) {
-                                self.errors = true;
-                                let slot = self
-                                    .seen
-                                    .entry(letter_color.to_string())
-                                    .or_insert_with(|| Default::default());
-                                if !slot.contains(&colored_text) {
-                                    slot.insert(colored_text);
-                                    println!("{}: {}", letter_color, colored_text_orig);
-                                }
-                                return;
-                            } else {
-                                // println!("{:?} matched {}", colored_text, pattern);
-                            }
-                        }
-                        // println!(
-                        //     "hi we are at around {:?}",
-                        //     &existing_text[(node.start().max(10) - 10)
-                        //         ..((node.end() + 10).min(existing_text.len()))]
-                        // );
-                        self.replacements.push((
-                            letter_color.to_string(),
-                            parameters[0].start..parameters[0].end,
-                        ));
-                    }
-                    _ => {}
-                }
+    fn selector(&self) -> Option<&Selector> {
+        Some(self.selector)
+    }
+    fn visit_selected(&mut self, element: &Element) {
+        let node = element.node.unwrap();
+        let mut template = TemplateView::new(node, self.base_text).unwrap();
+        let set_color = template.get("1").unwrap_or("");
+        let letter_color = match set_color {
+            "1" | "P" => "P",
+            "2" | "K" => "K",
+            "3" | "S" => "S",
+            "4" | "H" => "H",
+            "5" | "D" => "D",
+            "6" | "7" | "8" => return, //leave it alone
+            "added" | "new" | "removed" | "reworked" | "buff" | "nerf" => return, // leave alone
+            "green" | "purple" => return, // ditto
+            _ => {
+                self.diagnostics.error(
+                    "color/unknown-color",
+                    node.range(),
+                    format!("unknown color {:?}", set_color),
+                );
+                return;
             }
-            _ => unreachable!(),
         };
+        if ["P", "K", "S", "H", "D"].contains(&set_color) {
+            return;
+        }
+        if template.len() != 2 {
+            self.diagnostics.error(
+                "color/invalid-usage",
+                node.range(),
+                format!("not a valid color usage: {:?}", node.as_str(self.base_text)),
+            );
+            return;
+        }
+        let colored_text_orig = template.get("2").unwrap_or("");
+        let colored_text = colored_text_orig.to_lowercase();
+        let config = &self.prepared.config;
+        if config.nonmoves.iter().any(|m| m == &colored_text) {
+            return;
+        }
+        if config.moves[letter_color]
+            .iter()
+            .any(|m| m == &colored_text)
+        {
+            // ok
+        } else if config.skip.contains(colored_text.as_str()) {
+            // ok for now
+        } else {
+            let re = &self.prepared.regexes[letter_color];
+            if !re.is_match(&colored_text) {
+                self.diagnostics.error(
+                    "color/unknown-move",
+                    node.range(),
+                    format!("{}: {}", letter_color, colored_text_orig),
+                );
+                return;
+            } else {
+                // println!("{:?} matched {}", colored_text, pattern);
+            }
+        }
+        template.set("1", letter_color);
+        self.replacements.extend(template.replacements());
     }
 }
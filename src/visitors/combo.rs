@@ -1,49 +1,67 @@
+use crate::diagnostics::{Diagnostic, DiagnosticSink};
 use crate::parse::WikiVisitor;
-use crate::prelude::*;
+use crate::table_grid::TableGrid;
+use crate::transform::{Job, PagesSpec, Transform};
 use parse_wiki_text::Node;
 
-#[derive(Default)]
-pub struct ComboTableVisitor {
-    base_text: String,
-    in_table: bool,
-    skip_table: bool,
-    column_order: Option<Vec<String>>,
+pub struct ComboTableVisitor<'a> {
+    base_text: &'a str,
     replacements: Vec<(String, std::ops::Range<usize>)>,
-    errors: bool,
+    diagnostics: DiagnosticSink,
 }
-impl ComboTableVisitor {
-    pub fn new() -> Self {
-        Self::default()
+impl<'a> ComboTableVisitor<'a> {
+    pub fn new(base_text: &'a str) -> Self {
+        Self {
+            base_text,
+            replacements: Default::default(),
+            diagnostics: Default::default(),
+        }
     }
 }
 
-impl WikiVisitor for ComboTableVisitor {
-    fn set_base_text(&mut self, base_text: &str) {
-        self.base_text = base_text.to_string();
+pub struct ComboTableTransform;
+
+impl Transform for ComboTableTransform {
+    const NAME: &'static str = "combo";
+    const SUMMARY: &'static str = "Use combo table templates";
+    type Visitor<'a> = ComboTableVisitor<'a>;
+    type Config = ();
+
+    fn load(_config: Option<()>) -> anyhow::Result<Self> {
+        Ok(Self)
     }
-    fn get_replacements(&self) -> anyhow::Result<&[(String, std::ops::Range<usize>)]> {
-        if self.errors {
-            anyhow::bail!("Failed to do the thing. See errors above.");
+    fn job(&self) -> Job {
+        Job {
+            pages: Some(PagesSpec::List(vec!["GGST/Jack-O/Combos".to_string()])),
+            ..Default::default()
         }
-        Ok(&self.replacements)
     }
-    fn visit_table_start(&mut self, _: &Node) {
-        assert!(!self.in_table);
-        self.in_table = true;
-        self.skip_table = false;
-        self.column_order = None;
+    fn visitor<'a>(
+        &'a self,
+        _title: &'a str,
+        text: &'a str,
+    ) -> anyhow::Result<ComboTableVisitor<'a>> {
+        Ok(ComboTableVisitor::new(text))
     }
-    fn visit_table_end(&mut self, _: &Node) {
-        assert!(self.in_table);
-        self.skip_table = false;
-        self.in_table = false;
+}
+
+impl WikiVisitor for ComboTableVisitor<'_> {
+    fn take_replacements(&mut self) -> Vec<(String, std::ops::Range<usize>)> {
+        std::mem::take(&mut self.replacements)
     }
-    fn visit_table_row(&mut self, row: &parse_wiki_text::TableRow) {
-        if row.cells.len() == 0 {
-            //either the last row OR templatized already
-            return;
-        }
-        if row.cells.len() <= 3 || self.skip_table {
+    fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.diagnostics.take()
+    }
+    fn touches(&self) -> Option<&'static [&'static str]> {
+        Some(&["Table", "Template"])
+    }
+    fn visit_table_start(&mut self, node: &Node) {
+        let grid = TableGrid::new(node, self.base_text).unwrap();
+        let header = match grid.header_row() {
+            Some(h) => h,
+            None => return,
+        };
+        if grid.columns().len() <= 3 {
             // not a combo
             return;
         }
@@ -61,74 +79,86 @@ impl WikiVisitor for ComboTableVisitor {
             // "checkedVersion",
         ];
 
-        let mut out = String::new();
-        if self.column_order.is_none() {
-            // header
-            let mut order = vec![];
-            // must be the heading
-            assert!(row
-                .cells
-                .iter()
-                .all(|c| c.type_ == parse_wiki_text::TableCellType::Heading));
-            for cell in &row.cells {
-                let caption = cell.text_content(&self.base_text).to_lowercase();
-
-                order.push(match caption.as_str() {
-                    "combo" => "combo",
-                    "position" => "position",
-                    "damage" => "damage",
-                    "tension gain" => "tensionGain",
-                    "works on:" | "works on" => "worksOn",
-                    "difficulty" => "difficulty",
-                    "video" | "video demonstration" => "video",
-                    "notes" => "notes",
-                    "recipe" | "combo recipe no." | "recipe id" | "recipes (pc)"
-                    | "combo recipe (pc)" => "recipePC",
-                    "notation" => {
-                        self.skip_table = true;
-                        return;
-                    }
-                    z => panic!("unknown caption {:?}", z),
-                });
-                if let Some(a) = &cell.attributes {
-                    if caption.as_str() == "recipe"
-                        && a.len() == 1
-                        && a[0].as_str(&self.base_text).to_lowercase() == "colspan=2"
-                    {
-                        order.push("recipePS");
-                    }
+        let mut column_order = vec![];
+        for (col, caption) in grid.columns().iter().enumerate() {
+            let column = match caption.as_str() {
+                "combo" => "combo",
+                "position" => "position",
+                "damage" => "damage",
+                "tension gain" => "tensionGain",
+                "works on:" | "works on" => "worksOn",
+                "difficulty" => "difficulty",
+                "video" | "video demonstration" => "video",
+                "notes" => "notes",
+                // `colspan=2` recipe header covers both platforms
+                "recipe" if column_order.contains(&"recipePC") => "recipePS",
+                "recipe" | "combo recipe no." | "recipe id" | "recipes (pc)"
+                | "combo recipe (pc)" => "recipePC",
+                "notation" => return,
+                z => {
+                    let cell = grid.cell(header, col).unwrap();
+                    self.diagnostics.error(
+                        "combo/unknown-caption",
+                        cell.range.clone(),
+                        format!("unknown caption {:?}", z),
+                    );
+                    return;
                 }
-            }
-            // assert_eq!(
-            //     row.cells.len(),
-            //     order.len(),
-            //     "{:?} vs\n{:?}",
-            //     row.as_str(&self.base_text),
-            //     order,
-            // );
-            self.column_order = Some(order.into_iter().map(|x| x.to_string()).collect());
+            };
+            column_order.push(column);
+        }
+        self.replacements.push((
+            "|-\n{{GGST-ComboTableHeader}}".to_string(),
+            grid.row_range(header),
+        ));
 
-            out += "|-\n{{GGST-ComboTableHeader}}"
-        } else {
-            // non-header
-            if row.cells.len() != self.column_order.as_ref().unwrap().len() {
-                println!(
-                    "row length mismatch: {} vs {}: {}",
-                    row.cells.len(),
-                    self.column_order.as_ref().unwrap().len(),
-                    row.as_str(&self.base_text)
+        for r in header + 1..grid.row_count() {
+            let row = grid.row(r);
+            let mut distinct = row
+                .iter()
+                .flatten()
+                .map(|c| c.range.start)
+                .collect::<Vec<_>>();
+            distinct.dedup();
+            if distinct.len() <= 3 {
+                // a divider or a note spanning the table, not a combo
+                continue;
+            }
+            if row.len() != column_order.len() || row.iter().any(|c| c.is_none()) {
+                let row_range = grid.row_range(r);
+                self.diagnostics.error(
+                    "combo/row-length-mismatch",
+                    row_range.clone(),
+                    format!(
+                        "row length mismatch: {} columns vs {} in the header: {}",
+                        row.iter().flatten().count(),
+                        column_order.len(),
+                        &self.base_text[row_range],
+                    ),
                 );
-                self.errors = true;
-                return;
+                continue;
+            }
+            if let Some(cell) = row.iter().flatten().find(|c| c.colspan > 1) {
+                self.diagnostics.error(
+                    "combo/colspan",
+                    cell.range.clone(),
+                    format!(
+                        "cell spans {} columns, which can't go in one parameter: {}",
+                        cell.colspan,
+                        grid.text(cell).trim(),
+                    ),
+                );
+                continue;
             }
 
             let mut kvs = std::collections::BTreeMap::new();
             // kvs.insert("checkedVersion", "");
 
-            for (cell, column) in row.cells.iter().zip(self.column_order.as_ref().unwrap()) {
-                kvs.insert(column.as_str(), cell.text_content(&self.base_text));
+            for (cell, column) in row.iter().flatten().zip(&column_order) {
+                kvs.insert(*column, grid.text(cell));
             }
 
+            let mut out = String::new();
             out += "|-\n{{GGST-ComboTableRow\n";
             for col in desired_order {
                 let mut val = kvs.remove(col);
@@ -141,18 +171,25 @@ impl WikiVisitor for ComboTableVisitor {
                         // continue;
                         val = ""
                     }
-                    if val == "" {
+                    if val.is_empty() {
                         // continue;
                     }
 
                     out += &format!("|{} = {}\n", col, val)
                 }
             }
-            out += &"|checkedVersion = \n";
+            out += "|checkedVersion = \n";
             out += "}}";
-            assert!(kvs.is_empty(), "Some keys left over! {:?}", kvs);
-        }
+            if !kvs.is_empty() {
+                self.diagnostics.error(
+                    "combo/leftover-columns",
+                    grid.row_range(r),
+                    format!("columns with nowhere to go: {:?}", kvs),
+                );
+                continue;
+            }
 
-        self.replacements.push((out, row.start..row.end));
+            self.replacements.push((out, grid.row_range(r)));
+        }
     }
 }
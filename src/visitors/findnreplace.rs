@@ -1,28 +1,32 @@
 use crate::parse::WikiVisitor;
+use crate::transform::{EditSettings, Job, PagesSpec, Transform};
+use anyhow::Context;
 
-pub struct FindReplaceVisitor {
-    base_text: String,
-    config: FindReplaceConfig,
+pub struct FindReplaceVisitor<'a> {
+    base_text: &'a str,
+    transform: &'a FindReplaceTransform,
     replacements: Vec<(String, std::ops::Range<usize>)>,
     // errors: bool,
 }
-impl FindReplaceVisitor {
-    pub fn new(config: &FindReplaceConfig) -> Self {
-        Self {
-            config: config.clone(),
-            base_text: Default::default(),
+impl<'a> FindReplaceVisitor<'a> {
+    fn new(transform: &'a FindReplaceTransform, base_text: &'a str) -> Self {
+        let mut visitor = Self {
+            base_text,
+            transform,
             replacements: Default::default(),
-        }
+        };
+        visitor.gen_replacements();
+        visitor
     }
     fn gen_replacements(&mut self) {
-        for pred in &self.config.predicates {
+        let config = &self.transform.job.changes;
+        for pred in &config.predicates {
             if !self.base_text.contains(pred) {
                 return; // don't change anything
             }
         }
-        for (find, replace) in &self.config.re_patterns {
-            let re = regex::Regex::new(find).unwrap();
-            for cap in re.captures_iter(&self.base_text) {
+        for (re, replace) in &self.transform.re_patterns {
+            for cap in re.captures_iter(self.base_text) {
                 let m = cap.get(0).expect("capture 0 must always exist wtf");
                 // let fragment = m.as_str().to_string();
                 let mut fragment = String::new();
@@ -31,7 +35,7 @@ impl FindReplaceVisitor {
                 // println!("{:?}", self.replacements.last().unwrap());
             }
         }
-        for (find, replace) in &self.config.plain_patterns {
+        for (find, replace) in &config.plain_patterns {
             let found = self.base_text.find(find);
             let found = match found {
                 Some(f) => f,
@@ -50,12 +54,51 @@ pub struct FindReplaceConfig {
 }
 impl FindReplaceConfig {}
 
-impl WikiVisitor for FindReplaceVisitor {
-    fn set_base_text(&mut self, base_text: &str) {
-        self.base_text = base_text.to_string();
-        self.gen_replacements();
+/// A `--config` file for `findnreplace`.
+#[derive(serde::Deserialize)]
+pub struct FindReplaceJob {
+    pages: PagesSpec,
+    changes: FindReplaceConfig,
+    #[serde(flatten)]
+    edit: EditSettings,
+}
+
+/// The job, with its `re_patterns` compiled.
+pub struct FindReplaceTransform {
+    job: FindReplaceJob,
+    re_patterns: Vec<(regex::Regex, String)>,
+}
+
+impl Transform for FindReplaceTransform {
+    const NAME: &'static str = "findnreplace";
+    const SUMMARY: &'static str = "";
+    type Visitor<'a> = FindReplaceVisitor<'a>;
+    type Config = FindReplaceJob;
+
+    fn load(job: Option<FindReplaceJob>) -> anyhow::Result<Self> {
+        let job = job.context("specify --config")?;
+        let re_patterns = job
+            .changes
+            .re_patterns
+            .iter()
+            .map(|(find, replace)| Ok((regex::Regex::new(find)?, replace.clone())))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { job, re_patterns })
+    }
+    fn job(&self) -> Job {
+        self.job.edit.job(&self.job.pages)
+    }
+    fn visitor<'a>(
+        &'a self,
+        _title: &'a str,
+        text: &'a str,
+    ) -> anyhow::Result<FindReplaceVisitor<'a>> {
+        Ok(FindReplaceVisitor::new(self, text))
     }
-    fn get_replacements(&self) -> anyhow::Result<&[(String, std::ops::Range<usize>)]> {
-        Ok(&self.replacements)
+}
+
+impl WikiVisitor for FindReplaceVisitor<'_> {
+    fn take_replacements(&mut self) -> Vec<(String, std::ops::Range<usize>)> {
+        std::mem::take(&mut self.replacements)
     }
 }
@@ -2,7 +2,15 @@ mod color;
 mod combo;
 mod findnreplace;
 pub mod movecard;
+mod relink;
+mod retarget;
+mod script;
+mod urls;
 
-pub use combo::ComboTableVisitor;
-pub use color::{ColorConfig, ColorVisitor};
-pub use findnreplace::{FindReplaceVisitor, FindReplaceConfig};
\ No newline at end of file
+pub use color::ColorTransform;
+pub use combo::ComboTableTransform;
+pub use findnreplace::FindReplaceTransform;
+pub use relink::{normalize_title, RelinkTransform};
+pub use retarget::RetargetTransform;
+pub use script::ScriptTransform;
+pub use urls::UrlTransform;
@@ -1,30 +1,53 @@
 use crate::parse::WikiVisitor;
 use crate::prelude::*;
+use crate::transform::Transform;
 use parse_wiki_text::Node;
 
-#[derive(Default)]
-pub struct MoveCardVisitor {
-    base_text: String,
+pub struct MoveCardVisitor<'a> {
+    base_text: &'a str,
     replacements: Vec<(String, std::ops::Range<usize>)>,
     block_start: usize,
     descr_start: usize,
     is_ai: bool,
 }
-impl MoveCardVisitor {
-    pub fn new() -> Self {
-        Self::default()
+impl<'a> MoveCardVisitor<'a> {
+    pub fn new(base_text: &'a str) -> Self {
+        Self {
+            base_text,
+            replacements: Default::default(),
+            block_start: 0,
+            descr_start: 0,
+            is_ai: false,
+        }
     }
 }
 
-impl WikiVisitor for MoveCardVisitor {
-    fn set_base_text(&mut self, base_text: &str) {
-        self.base_text = base_text.to_string();
+pub struct MoveCardTransform;
+
+impl Transform for MoveCardTransform {
+    const NAME: &'static str = "movecard";
+    const SUMMARY: &'static str = "Use GGST Move Card template";
+    type Visitor<'a> = MoveCardVisitor<'a>;
+    type Config = ();
+
+    fn load(_config: Option<()>) -> anyhow::Result<Self> {
+        Ok(Self)
+    }
+    fn visitor<'a>(
+        &'a self,
+        _title: &'a str,
+        text: &'a str,
+    ) -> anyhow::Result<MoveCardVisitor<'a>> {
+        Ok(MoveCardVisitor::new(text))
     }
-    fn get_replacements(&self) -> anyhow::Result<&[(String, std::ops::Range<usize>)]> {
-        Ok(self.replacements.as_slice())
+}
+
+impl WikiVisitor for MoveCardVisitor<'_> {
+    fn take_replacements(&mut self) -> Vec<(String, std::ops::Range<usize>)> {
+        std::mem::take(&mut self.replacements)
     }
     fn visit_start_tag(&mut self, node: &Node) {
-        let node_str = node.as_str(&self.base_text);
+        let node_str = node.as_str(self.base_text);
         if node_str == r#"<div class="attack-container">"# {
             self.block_start = node.start();
             self.is_ai = false;
@@ -33,17 +56,15 @@ impl WikiVisitor for MoveCardVisitor {
         }
     }
     fn visit_heading(&mut self, node: &Node) {
-        if node.as_str(&self.base_text) == "==== ====" {
-            if self.is_ai {
-                self.is_ai = false;
-                self.descr_start = node.end();
-            }
+        if node.as_str(self.base_text) == "==== ====" && self.is_ai {
+            self.is_ai = false;
+            self.descr_start = node.end();
         }
     }
     fn visit_template(&mut self, node: &Node) {
         match node {
             Node::Template { name, .. } => {
-                if name.as_str(&self.base_text) != "CloseCard" {
+                if name.as_str(self.base_text) != "CloseCard" {
                     return;
                 }
             }
@@ -54,7 +75,7 @@ impl WikiVisitor for MoveCardVisitor {
 
         let descr_start = self.descr_start;
         let descr_end = node.start() - 1;
-        // println!("{}\nxxxxxxxxx\n", &self.base_text[descr_start..descr_end]);
+        // println!("{}\nxxxxxxxxx\n", self.base_text[descr_start..descr_end]);
         let descr = self.base_text[descr_start..descr_end].trim();
         let mut out = String::new();
         out += "{{GGST Move Card\n|input=\n|description=\n";
@@ -0,0 +1,142 @@
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::parse::WikiVisitor;
+use crate::transform::Transform;
+use anyhow::Context;
+use parse_wiki_text::Node;
+use std::collections::BTreeMap;
+
+/// How the wiki compares titles: `_` is a space, runs of spaces are one, and
+/// the first letter is always upper case.
+pub fn normalize_title(title: &str) -> String {
+    let title = title.replace('_', " ");
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut chars = title.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => title,
+    }
+}
+
+/// A `--config` file for `relink`, for when the pages were moved by hand.
+#[derive(serde::Deserialize)]
+pub struct RelinkConfig {
+    /// Old title to new title.
+    moves: BTreeMap<String, String>,
+}
+
+/// Points `[[Old]]`, `[[old_title#anchor|label]]` and the like at the new titles,
+/// keeping anchors and what the links display. A new title can have an anchor
+/// of its own, for links that don't.
+///
+/// Only plain links are rewritten, not `link=` of images or titles passed to
+/// templates, so pages that are known to link to an old title are reported
+/// when nothing was.
+pub struct RelinkTransform {
+    /// Keyed by the normalized old title.
+    moves: BTreeMap<String, String>,
+    /// Whether every page links to one of the old titles.
+    backlinks: bool,
+}
+
+impl RelinkTransform {
+    pub fn new(moves: impl IntoIterator<Item = (String, String)>) -> Self {
+        Self {
+            moves: moves
+                .into_iter()
+                .map(|(from, to)| (normalize_title(&from), to))
+                .collect(),
+            backlinks: false,
+        }
+    }
+
+    /// For when the pages are backlinks of the old titles.
+    pub fn for_backlinks(self) -> Self {
+        Self {
+            backlinks: true,
+            ..self
+        }
+    }
+}
+
+impl Transform for RelinkTransform {
+    const NAME: &'static str = "relink";
+    const SUMMARY: &'static str = "Update links to moved pages";
+    type Visitor<'a> = RelinkVisitor<'a>;
+    type Config = RelinkConfig;
+
+    fn load(config: Option<RelinkConfig>) -> anyhow::Result<Self> {
+        let config = config.context("specify --config")?;
+        Ok(Self::new(config.moves))
+    }
+    fn visitor<'a>(&'a self, _title: &'a str, text: &'a str) -> anyhow::Result<RelinkVisitor<'a>> {
+        Ok(RelinkVisitor {
+            moves: &self.moves,
+            backlinks: self.backlinks,
+            base_text: text,
+            replacements: vec![],
+        })
+    }
+}
+
+pub struct RelinkVisitor<'a> {
+    moves: &'a BTreeMap<String, String>,
+    backlinks: bool,
+    base_text: &'a str,
+    replacements: Vec<(String, std::ops::Range<usize>)>,
+}
+
+impl WikiVisitor for RelinkVisitor<'_> {
+    fn take_replacements(&mut self) -> Vec<(String, std::ops::Range<usize>)> {
+        std::mem::take(&mut self.replacements)
+    }
+    fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        if !self.backlinks || !self.replacements.is_empty() {
+            return vec![];
+        }
+        vec![Diagnostic::new(
+            Severity::Warning,
+            "relink/unchanged",
+            0..0,
+            "no link was rewritten, the old title may be linked through a template, \
+             an image's link= or the like",
+        )]
+    }
+    fn touches(&self) -> Option<&'static [&'static str]> {
+        Some(&["Link"])
+    }
+    fn visit_link(&mut self, node: &Node) {
+        let start = match node {
+            Node::Link { start, .. } => start + "[[".len(),
+            _ => return,
+        };
+        // the parser hands out the target trimmed, so find it in the source
+        let rest = &self.base_text[start..];
+        let len = match rest.find(['|', ']']) {
+            Some(len) => len,
+            None => return,
+        };
+        let labelled = rest[len..].starts_with('|');
+        let raw = &rest[..len];
+        let (title, anchor) = raw.split_at(raw.find('#').unwrap_or(raw.len()));
+        let trimmed = title.trim();
+        let (colon, name) = match trimmed.strip_prefix(':') {
+            Some(name) => (":", name),
+            None => ("", trimmed),
+        };
+        let new = match self.moves.get(&normalize_title(name)) {
+            // the link's own anchor wins over the new title's
+            Some(new) if !anchor.is_empty() => new.split('#').next().unwrap(),
+            Some(new) => new.as_str(),
+            None => return,
+        };
+        let lead = &title[..title.len() - title.trim_start().len()];
+        let trail = &title[title.trim_end().len()..];
+        let mut replacement = format!("{}{}{}{}{}", lead, colon, new, trail, anchor);
+        // an unlabelled link showed its target, so keep showing that
+        let shown = raw.trim().trim_start_matches(':');
+        if !labelled && shown != format!("{}{}", new, anchor.trim_end()) {
+            replacement = format!("{}|{}", replacement, shown);
+        }
+        self.replacements.push((replacement, start..start + len));
+    }
+}
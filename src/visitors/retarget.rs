@@ -0,0 +1,67 @@
+use crate::parse::WikiVisitor;
+use crate::transform::Transform;
+use parse_wiki_text::Node;
+use std::collections::BTreeMap;
+
+/// Points redirect pages somewhere else. Made by `--mode redirects`, which
+/// works out where.
+pub struct RetargetTransform {
+    /// Page title to its new target.
+    targets: BTreeMap<String, String>,
+}
+
+impl RetargetTransform {
+    pub fn new(targets: BTreeMap<String, String>) -> Self {
+        Self { targets }
+    }
+}
+
+impl Transform for RetargetTransform {
+    const NAME: &'static str = "retarget";
+    const SUMMARY: &'static str = "Fix double redirect";
+    type Visitor<'a> = RetargetVisitor<'a>;
+    type Config = ();
+
+    fn load(_config: Option<()>) -> anyhow::Result<Self> {
+        anyhow::bail!("retarget is run by --mode redirects")
+    }
+    fn visitor<'a>(&'a self, title: &'a str, text: &'a str) -> anyhow::Result<RetargetVisitor<'a>> {
+        Ok(RetargetVisitor {
+            target: self.targets.get(title),
+            base_text: text,
+            replacements: vec![],
+        })
+    }
+}
+
+pub struct RetargetVisitor<'a> {
+    target: Option<&'a String>,
+    base_text: &'a str,
+    replacements: Vec<(String, std::ops::Range<usize>)>,
+}
+
+impl WikiVisitor for RetargetVisitor<'_> {
+    fn take_replacements(&mut self) -> Vec<(String, std::ops::Range<usize>)> {
+        std::mem::take(&mut self.replacements)
+    }
+    fn touches(&self) -> Option<&'static [&'static str]> {
+        Some(&["Redirect"])
+    }
+    fn visit_redirect(&mut self, node: &Node) {
+        let (target, start, end) = match (node, self.target) {
+            (Node::Redirect { start, end, .. }, Some(target)) => (target, *start, *end),
+            _ => return,
+        };
+        // `#REDIRECT [[target]]`, maybe with a `|label` the wiki ignores
+        let source = &self.base_text[start..end];
+        let open = match source.find("[[") {
+            Some(open) => open + "[[".len(),
+            None => return,
+        };
+        let len = source[open..]
+            .find(['|', ']'])
+            .unwrap_or(source.len() - open);
+        self.replacements
+            .push((target.clone(), start + open..start + open + len));
+    }
+}
@@ -0,0 +1,577 @@
+//! `--mode script`: a Rhai script gets the visitor hooks.
+//!
+//! The job file names the script and the pages, plus the usual `apply`,
+//! `comment` and `isminor`:
+//!
+//! ```text
+//! { script: "scripts/clr.rhai", pages: { prefix: "GGST/" }, comment: "..." }
+//! ```
+//!
+//! The script defines whichever hooks it needs, each taking one argument:
+//! `page(text)`, `template(t)`, `table(grid)`, `heading(n)`, `text(n)`,
+//! `start_tag(n)`, `external_link(n)`, `image(n)`, `category(n)`,
+//! `redirect(n)` and `magic_word(n)`.
+//!
+//! - `t` is a template view: `t.name`, `t.get(key)`, `t.positional()`,
+//!   `t.named()`, `t.len()`, and edits `t.set(key, value)`, `t.rename(key, name)`,
+//!   `t.insert(name, value)`, `t.insert_after(anchor, name, value)`,
+//!   `t.remove(key)`, `t.set_name(name)`. Reads see the page as it was.
+//! - `grid` is a map with `columns`, `header_row` and `rows`, each row an array
+//!   of cell maps (`text`, `start`, `end`, `content_start`, `content_end`, `row`,
+//!   `col`, `rowspan`, `colspan`, `heading`), spanned cells repeated.
+//! - `n` is a map with `kind`, `text`, `start` and `end`, and `level`/`title` for
+//!   headings, `name` for tags, `target` for images, categories and redirects.
+//!
+//! Anywhere, `replace(start, end, text)` replaces a byte range of the page and
+//! `error(code, start, end, message)`, `warning(..)` and `info(..)` report.
+//! `title` is the page being worked on.
+//!
+//! Top-level statements run once per page, before any hook, and hooks see
+//! the variables and constants they declare by name. Variables keep their
+//! values from one hook call to the next, within the page.
+
+use crate::diagnostics::{Diagnostic, DiagnosticSink, Severity};
+use crate::parse::WikiVisitor;
+use crate::prelude::*;
+use crate::table_grid::{GridCell, TableGrid};
+use crate::template_view::TemplateView;
+use crate::transform::{EditSettings, Job, PagesSpec, Transform};
+use anyhow::Context;
+use parse_wiki_text::Node;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST, INT};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::rc::Rc;
+
+const HOOKS: &[&str] = &[
+    "page",
+    "template",
+    "table",
+    "heading",
+    "text",
+    "start_tag",
+    "external_link",
+    "image",
+    "category",
+    "redirect",
+    "magic_word",
+];
+
+#[derive(serde::Deserialize)]
+pub struct ScriptJob {
+    script: String,
+    pages: PagesSpec,
+    #[serde(flatten)]
+    edit: EditSettings,
+}
+
+/// Whatever the script pushed, shared with the functions registered on the engine.
+/// Ranges are as the script gave them, the visitor checks them against its page.
+#[derive(Default)]
+struct Pushed {
+    replacements: Vec<(String, INT, INT)>,
+    diagnostics: Vec<(Severity, String, INT, INT, String)>,
+}
+
+/// The compiled script and an engine to run it, shared by all pages.
+pub struct ScriptTransform {
+    job: ScriptJob,
+    ast: AST,
+    /// The `HOOKS` the script defines.
+    hooks: BTreeSet<&'static str>,
+    engine: Engine,
+    pushed: Rc<RefCell<Pushed>>,
+}
+
+pub struct ScriptVisitor<'a> {
+    transform: &'a ScriptTransform,
+    scope: Scope<'static>,
+    /// Whether the top-level statements ran fine, and so hooks can be called.
+    started: bool,
+    base_text: &'a str,
+    replacements: Vec<(String, Range<usize>)>,
+    diagnostics: DiagnosticSink,
+}
+
+enum TemplateEdit {
+    SetName(String),
+    Set(String, String),
+    Rename(String, String),
+    Insert(String, String),
+    InsertAfter(String, String, String),
+    Remove(String),
+}
+
+/// What a script sees of a template. Edits are replayed on a `TemplateView` afterwards.
+#[derive(Clone)]
+struct ScriptTemplate {
+    name: String,
+    text: String,
+    range: Range<usize>,
+    params: Vec<(Option<String>, String)>,
+    edits: Rc<RefCell<Vec<TemplateEdit>>>,
+}
+
+impl ScriptTemplate {
+    fn new(view: &TemplateView) -> Self {
+        let mut params = view
+            .positional()
+            .into_iter()
+            .map(|v| (None, v.to_string()))
+            .collect::<Vec<_>>();
+        params.extend(
+            view.named()
+                .into_iter()
+                .map(|(k, v)| (Some(k.to_string()), v.to_string())),
+        );
+        Self {
+            name: view.name().to_string(),
+            text: view.as_str().to_string(),
+            range: view.range(),
+            params,
+            edits: Default::default(),
+        }
+    }
+
+    fn get(&self, key: &str) -> Dynamic {
+        let named = self.params.iter().find(|(k, _)| k.as_deref() == Some(key));
+        let found = named.or_else(|| {
+            let n: usize = key.parse().ok()?;
+            self.params
+                .iter()
+                .filter(|(k, _)| k.is_none())
+                .nth(n.checked_sub(1)?)
+        });
+        match found {
+            Some((_, v)) => v.clone().into(),
+            None => Dynamic::UNIT,
+        }
+    }
+
+    fn edit(&mut self, edit: TemplateEdit) {
+        self.edits.borrow_mut().push(edit);
+    }
+}
+
+fn int(n: usize) -> Dynamic {
+    (n as INT).into()
+}
+
+fn node_map(node: &Node, text: &str) -> Map {
+    let mut map = Map::new();
+    map.insert("kind".into(), node.kind().into());
+    map.insert("text".into(), node.as_str(text).to_string().into());
+    map.insert("start".into(), int(node.start()));
+    map.insert("end".into(), int(node.end()));
+    match node {
+        Node::Heading { level, nodes, .. } => {
+            map.insert("level".into(), int(*level as usize));
+            let title = if nodes.is_empty() {
+                ""
+            } else {
+                nodes.as_str(text).trim()
+            };
+            map.insert("title".into(), title.to_string().into());
+        }
+        Node::StartTag { name, .. } => {
+            map.insert("name".into(), name.to_string().into());
+        }
+        Node::Image { target, .. }
+        | Node::Category { target, .. }
+        | Node::Redirect { target, .. } => {
+            map.insert("target".into(), target.to_string().into());
+        }
+        _ => {}
+    }
+    map
+}
+
+fn cell_map(grid: &TableGrid, cell: &GridCell) -> Map {
+    let mut map = Map::new();
+    map.insert("text".into(), grid.text(cell).to_string().into());
+    map.insert("start".into(), int(cell.range.start));
+    map.insert("end".into(), int(cell.range.end));
+    map.insert("content_start".into(), int(cell.content.start));
+    map.insert("content_end".into(), int(cell.content.end));
+    map.insert("row".into(), int(cell.row));
+    map.insert("col".into(), int(cell.col));
+    map.insert("rowspan".into(), int(cell.rowspan));
+    map.insert("colspan".into(), int(cell.colspan));
+    map.insert("heading".into(), cell.heading.into());
+    map
+}
+
+fn grid_map(grid: &TableGrid) -> Map {
+    let mut map = Map::new();
+    map.insert("start".into(), int(grid.range().start));
+    map.insert("end".into(), int(grid.range().end));
+    let columns = grid
+        .columns()
+        .iter()
+        .map(|c| Dynamic::from(c.clone()))
+        .collect::<Array>();
+    map.insert("columns".into(), columns.into());
+    map.insert(
+        "header_row".into(),
+        grid.header_row().map(int).unwrap_or(Dynamic::UNIT),
+    );
+    let rows = (0..grid.row_count())
+        .map(|r| {
+            grid.row(r)
+                .into_iter()
+                .map(|c| match c {
+                    Some(c) => cell_map(grid, c).into(),
+                    None => Dynamic::UNIT,
+                })
+                .collect::<Array>()
+                .into()
+        })
+        .collect::<Array>();
+    map.insert("rows".into(), rows.into());
+    map
+}
+
+/// Byte range from script integers, if it makes sense for `text`: in bounds and
+/// not splitting a character.
+fn checked_range(text: &str, start: INT, end: INT) -> Result<Range<usize>, String> {
+    let fits = start >= 0
+        && end >= start
+        && text.is_char_boundary(start as usize)
+        && text.is_char_boundary(end as usize);
+    if !fits {
+        return Err(format!(
+            "bad range {}..{} (page is {} bytes)",
+            start,
+            end,
+            text.len()
+        ));
+    }
+    Ok(start as usize..end as usize)
+}
+
+fn sandboxed_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+    engine.set_max_operations(50_000_000);
+    engine.set_max_call_levels(64);
+    engine.set_max_string_size(10_000_000);
+    engine.disable_symbol("eval");
+    engine
+}
+
+fn register_template(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<ScriptTemplate>("Template")
+        .register_get("name", |t: &mut ScriptTemplate| t.name.clone())
+        .register_get("text", |t: &mut ScriptTemplate| t.text.clone())
+        .register_get("start", |t: &mut ScriptTemplate| t.range.start as INT)
+        .register_get("end", |t: &mut ScriptTemplate| t.range.end as INT)
+        .register_fn("len", |t: &mut ScriptTemplate| t.params.len() as INT)
+        .register_fn("get", |t: &mut ScriptTemplate, key: &str| t.get(key))
+        .register_fn("get", |t: &mut ScriptTemplate, key: INT| {
+            t.get(&key.to_string())
+        })
+        .register_fn("positional", |t: &mut ScriptTemplate| {
+            t.params
+                .iter()
+                .filter(|(k, _)| k.is_none())
+                .map(|(_, v)| Dynamic::from(v.clone()))
+                .collect::<Array>()
+        })
+        .register_fn("named", |t: &mut ScriptTemplate| {
+            t.params
+                .iter()
+                .filter_map(|(k, v)| Some((k.as_deref()?.into(), Dynamic::from(v.clone()))))
+                .collect::<Map>()
+        })
+        .register_fn("set_name", |t: &mut ScriptTemplate, name: &str| {
+            t.edit(TemplateEdit::SetName(name.to_string()))
+        })
+        .register_fn("set", |t: &mut ScriptTemplate, key: &str, value: &str| {
+            t.edit(TemplateEdit::Set(key.to_string(), value.to_string()))
+        })
+        .register_fn("set", |t: &mut ScriptTemplate, key: INT, value: &str| {
+            t.edit(TemplateEdit::Set(key.to_string(), value.to_string()))
+        })
+        .register_fn("rename", |t: &mut ScriptTemplate, key: &str, name: &str| {
+            t.edit(TemplateEdit::Rename(key.to_string(), name.to_string()))
+        })
+        .register_fn(
+            "insert",
+            |t: &mut ScriptTemplate, name: &str, value: &str| {
+                t.edit(TemplateEdit::Insert(name.to_string(), value.to_string()))
+            },
+        )
+        .register_fn(
+            "insert_after",
+            |t: &mut ScriptTemplate, anchor: &str, name: &str, value: &str| {
+                t.edit(TemplateEdit::InsertAfter(
+                    anchor.to_string(),
+                    name.to_string(),
+                    value.to_string(),
+                ))
+            },
+        )
+        .register_fn("remove", |t: &mut ScriptTemplate, key: &str| {
+            t.edit(TemplateEdit::Remove(key.to_string()))
+        });
+}
+
+fn register_output(engine: &mut Engine, pushed: &Rc<RefCell<Pushed>>) {
+    let p = pushed.clone();
+    engine.register_fn("replace", move |start: INT, end: INT, text: &str| {
+        p.borrow_mut()
+            .replacements
+            .push((text.to_string(), start, end));
+    });
+    for (name, severity) in [
+        ("error", Severity::Error),
+        ("warning", Severity::Warning),
+        ("info", Severity::Info),
+    ] {
+        let p = pushed.clone();
+        engine.register_fn(
+            name,
+            move |code: &str, start: INT, end: INT, message: &str| {
+                let code = format!("script/{}", code);
+                p.borrow_mut()
+                    .diagnostics
+                    .push((severity, code, start, end, message.to_string()));
+            },
+        );
+    }
+}
+
+impl Transform for ScriptTransform {
+    const NAME: &'static str = "script";
+    const SUMMARY: &'static str = "";
+    type Visitor<'a> = ScriptVisitor<'a>;
+    type Config = ScriptJob;
+
+    fn load(job: Option<ScriptJob>) -> anyhow::Result<Self> {
+        let job = job.context("specify --config")?;
+        let source = std::fs::read_to_string(&job.script)
+            .with_context(|| format!("reading {}", job.script))?;
+        let mut engine = sandboxed_engine();
+        let ast = engine
+            .compile(&source)
+            .map_err(|e| anyhow::anyhow!("{}: {}", job.script, e))?;
+        let hooks = HOOKS
+            .iter()
+            .copied()
+            .filter(|h| {
+                ast.iter_functions()
+                    .any(|f| f.name == *h && f.params.len() == 1)
+            })
+            .collect::<BTreeSet<_>>();
+        if hooks.is_empty() {
+            anyhow::bail!("{} defines none of the hooks {:?}", job.script, HOOKS);
+        }
+        let pushed = Rc::new(RefCell::new(Pushed::default()));
+        register_template(&mut engine);
+        register_output(&mut engine, &pushed);
+        Ok(Self {
+            job,
+            ast,
+            hooks,
+            engine,
+            pushed,
+        })
+    }
+    fn job(&self) -> Job {
+        self.job.edit.job(&self.job.pages)
+    }
+    fn visitor<'a>(&'a self, title: &'a str, text: &'a str) -> anyhow::Result<ScriptVisitor<'a>> {
+        let mut scope = Scope::new();
+        scope.push_constant("title", title.to_string());
+        Ok(ScriptVisitor {
+            transform: self,
+            scope,
+            started: false,
+            base_text: text,
+            replacements: vec![],
+            diagnostics: Default::default(),
+        })
+    }
+}
+
+impl ScriptVisitor<'_> {
+    /// Runs the script's top-level statements, leaving the hooks to call.
+    fn start_page(&mut self) {
+        let transform = self.transform;
+        match transform
+            .engine
+            .run_ast_with_scope(&mut self.scope, &transform.ast)
+        {
+            Ok(()) => self.started = true,
+            Err(e) => self
+                .diagnostics
+                .error("script/error", 0..0, format!("top level: {}", e)),
+        }
+        self.collect("top level", 0..0);
+    }
+
+    /// Calls the hook if the script has it. Failures become `script/error` at `span`.
+    fn call(&mut self, hook: &str, arg: Dynamic, span: Range<usize>) {
+        let transform = self.transform;
+        if !self.started || !transform.hooks.contains(hook) {
+            return;
+        }
+        let options = CallFnOptions::new().eval_ast(false).rewind_scope(true);
+        let result = transform.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut self.scope,
+            &transform.ast,
+            hook,
+            (arg,),
+        );
+        if let Err(e) = result {
+            self.diagnostics
+                .error("script/error", span.clone(), format!("{}: {}", hook, e));
+        }
+        self.collect(hook, span);
+    }
+
+    /// Moves what the script pushed into the visitor, refusing overlapping replacements
+    /// and ranges that don't fit the page. Those are reported at `span`.
+    fn collect(&mut self, hook: &str, span: Range<usize>) {
+        let pushed = std::mem::take(&mut *self.transform.pushed.borrow_mut());
+        for (severity, code, start, end, message) in pushed.diagnostics {
+            match checked_range(self.base_text, start, end) {
+                Ok(range) => self
+                    .diagnostics
+                    .emit(Diagnostic::new(severity, &code, range, message)),
+                Err(e) => {
+                    self.diagnostics
+                        .error("script/error", span.clone(), format!("{}: {}", hook, e))
+                }
+            }
+        }
+        for (text, start, end) in pushed.replacements {
+            match checked_range(self.base_text, start, end) {
+                Ok(range) => self.add_replacement(text, range),
+                Err(e) => {
+                    self.diagnostics
+                        .error("script/error", span.clone(), format!("{}: {}", hook, e))
+                }
+            }
+        }
+    }
+
+    fn add_replacement(&mut self, text: String, range: Range<usize>) {
+        let overlaps = self.replacements.iter().any(|(_, r)| {
+            r.start < range.end && range.start < r.end
+                || r == &range
+                || (r.is_empty() || range.is_empty()) && r.start == range.start
+        });
+        if overlaps {
+            self.diagnostics.error(
+                "script/overlap",
+                range.clone(),
+                format!("replacement overlaps an earlier one: {:?}", text),
+            );
+            return;
+        }
+        self.replacements.push((text, range));
+    }
+
+    fn call_node(&mut self, hook: &str, node: &Node) {
+        if !self.transform.hooks.contains(hook) {
+            return;
+        }
+        let map = node_map(node, self.base_text);
+        self.call(hook, map.into(), node.range());
+    }
+}
+
+impl WikiVisitor for ScriptVisitor<'_> {
+    fn visit_page_start(&mut self) {
+        self.start_page();
+        self.call("page", self.base_text.to_string().into(), 0..0);
+    }
+    fn take_replacements(&mut self) -> Vec<(String, std::ops::Range<usize>)> {
+        std::mem::take(&mut self.replacements)
+    }
+    fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.diagnostics.take()
+    }
+
+    fn visit_template(&mut self, node: &Node) {
+        if !self.transform.hooks.contains("template") {
+            return;
+        }
+        let view = TemplateView::new(node, self.base_text).unwrap();
+        let template = ScriptTemplate::new(&view);
+        let edits = template.edits.clone();
+        self.call("template", Dynamic::from(template), node.range());
+
+        let edits = std::mem::take(&mut *edits.borrow_mut());
+        if edits.is_empty() {
+            return;
+        }
+        let mut view = TemplateView::new(node, self.base_text).unwrap();
+        for edit in edits {
+            let ok = match &edit {
+                TemplateEdit::SetName(name) => {
+                    view.set_name(name);
+                    true
+                }
+                TemplateEdit::Set(key, value) => {
+                    view.set(key, value);
+                    true
+                }
+                TemplateEdit::Rename(key, name) => view.rename(key, name),
+                TemplateEdit::Insert(name, value) => {
+                    view.insert(name, value);
+                    true
+                }
+                TemplateEdit::InsertAfter(anchor, name, value) => {
+                    view.insert_after(anchor, name, value)
+                }
+                TemplateEdit::Remove(key) => view.remove(key),
+            };
+            if !ok {
+                self.diagnostics.error(
+                    "script/no-such-param",
+                    node.range(),
+                    "template edit refers to a parameter that isn't there",
+                );
+            }
+        }
+        for (text, range) in view.replacements() {
+            self.add_replacement(text, range);
+        }
+    }
+    fn visit_table_start(&mut self, node: &Node) {
+        if !self.transform.hooks.contains("table") {
+            return;
+        }
+        let map = grid_map(&TableGrid::new(node, self.base_text).unwrap());
+        self.call("table", map.into(), node.range());
+    }
+    fn visit_heading(&mut self, node: &Node) {
+        self.call_node("heading", node);
+    }
+    fn visit_text(&mut self, node: &Node) {
+        self.call_node("text", node);
+    }
+    fn visit_start_tag(&mut self, node: &Node) {
+        self.call_node("start_tag", node);
+    }
+    fn visit_external_link(&mut self, node: &Node) {
+        self.call_node("external_link", node);
+    }
+    fn visit_image(&mut self, node: &Node) {
+        self.call_node("image", node);
+    }
+    fn visit_category(&mut self, node: &Node) {
+        self.call_node("category", node);
+    }
+    fn visit_redirect(&mut self, node: &Node) {
+        self.call_node("redirect", node);
+    }
+    fn visit_magic_word(&mut self, node: &Node) {
+        self.call_node("magic_word", node);
+    }
+}
@@ -0,0 +1,325 @@
+use crate::diagnostics::{Diagnostic, DiagnosticSink, Severity};
+use crate::parse::WikiVisitor;
+use crate::prelude::*;
+use crate::transform::{Job, PagesSpec, Transform};
+use parse_wiki_text::Node;
+
+/// `%XX` escapes decoded, if that gives valid UTF-8.
+fn decode_percent(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        // `from_str_radix` would take a sign too
+        let escaped = s
+            .get(i + 1..i + 3)
+            .filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Query parameters that don't change which page is shown, or its revision.
+const HARMLESS_PARAMS: &[&str] = &["useskin", "uselang", "printable", "mobileaction"];
+
+/// The `title=` of a page view's query, still percent-encoded, if there's one.
+fn query_title(query: &str) -> Result<Option<String>, &'static str> {
+    let mut title = None;
+    for param in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = param.split_once('=').unwrap_or((param, ""));
+        match key {
+            // `+` is a space in queries, but not in paths
+            "title" => title = Some(value.replace('+', "%20")),
+            // a diff, an old revision, an edit form, ...
+            "diff" | "oldid" | "action" => return Err("not a plain page view"),
+            key if HARMLESS_PARAMS.contains(&key) => {}
+            _ => return Err("unknown query parameter"),
+        }
+    }
+    Ok(title)
+}
+
+/// The `[[...]]` target a dustloop URL stands for, or why there isn't one.
+/// Takes `/w/Title` and `/wiki/index.php?title=Title`, either with a `#anchor`
+/// and with `HARMLESS_PARAMS` in the query.
+fn link_target(url: &str) -> Result<String, &'static str> {
+    let rest = url
+        .trim_start_matches("http://")
+        .trim_start_matches("https://")
+        .trim_start_matches("www.")
+        .trim_start_matches("dustloop.com");
+    let (rest, fragment) = match rest.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(fragment)),
+        None => (rest, None),
+    };
+    let (path, query) = match rest.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (rest, None),
+    };
+    let query_title = query.map(query_title).transpose()?.flatten();
+    let title = match (path, query_title) {
+        (path, None) if path.starts_with("/w/") => path["/w/".len()..].to_string(),
+        (path, Some(_)) if path.starts_with("/w/") => return Err("a title in the query too"),
+        ("/wiki/index.php" | "/index.php", Some(title)) => title,
+        ("/wiki/index.php" | "/index.php", None) => return Err("no title= in the query"),
+        _ => return Err("not a page URL"),
+    };
+    // `_` is a space in titles
+    let title = decode_percent(&title).ok_or("bad percent-encoding")?;
+    let title = title.replace('_', " ").trim().to_string();
+    if title.is_empty() || title.contains(['#', '<', '>', '[', ']', '|', '{', '}']) {
+        return Err("not a valid title");
+    }
+    match fragment {
+        Some(fragment) if !fragment.is_empty() => {
+            let anchor = decode_percent(fragment).ok_or("bad percent-encoding")?;
+            Ok(format!("{}#{}", title, anchor.replace('_', " ")))
+        }
+        _ => Ok(title),
+    }
+}
+
+/// Links to dustloop pages spelled out as full URLs, made into wikilinks. Ones
+/// that aren't plain page views are reported instead.
+pub struct UrlTransform {
+    /// Free URLs end at whitespace and at what can't be in one.
+    url: regex::Regex,
+}
+
+impl Transform for UrlTransform {
+    const NAME: &'static str = "urls";
+    const SUMMARY: &'static str = "Link to dustloop pages by title instead of by URL";
+    type Visitor<'a> = UrlVisitor<'a>;
+    type Config = ();
+
+    fn load(_config: Option<()>) -> anyhow::Result<Self> {
+        Ok(Self {
+            url: regex::Regex::new(r#"https?://(www\.)?dustloop\.com[^\s\[\]<>"{}|]*"#)?,
+        })
+    }
+    fn job(&self) -> Job {
+        Job {
+            pages: Some(PagesSpec::Spec {
+                prefix: "GGST".to_string(),
+                pattern: None,
+                skip: vec![],
+            }),
+            ..Default::default()
+        }
+    }
+    fn visitor<'a>(&'a self, _title: &'a str, text: &'a str) -> anyhow::Result<UrlVisitor<'a>> {
+        Ok(UrlVisitor {
+            transform: self,
+            base_text: text,
+            claimed: vec![],
+            replacements: vec![],
+            diagnostics: Default::default(),
+        })
+    }
+}
+
+pub struct UrlVisitor<'a> {
+    transform: &'a UrlTransform,
+    base_text: &'a str,
+    /// External links, templates, images, links and tags seen so far, with why
+    /// a URL in them is left alone. External links have their own way.
+    claimed: Vec<(std::ops::Range<usize>, Option<&'static str>)>,
+    replacements: Vec<(String, std::ops::Range<usize>)>,
+    diagnostics: DiagnosticSink,
+}
+
+impl UrlVisitor<'_> {
+    fn unmapped(&mut self, url: &str, span: std::ops::Range<usize>, why: &str) {
+        self.diagnostics.emit(Diagnostic::new(
+            Severity::Warning,
+            "urls/unmapped",
+            span,
+            format!("can't make {} a wikilink: {}", url, why),
+        ));
+    }
+}
+
+impl WikiVisitor for UrlVisitor<'_> {
+    fn take_replacements(&mut self) -> Vec<(String, std::ops::Range<usize>)> {
+        std::mem::take(&mut self.replacements)
+    }
+    fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.diagnostics.take()
+    }
+    fn touches(&self) -> Option<&'static [&'static str]> {
+        Some(&["ExternalLink", "Link", "Text"])
+    }
+    fn visit_template(&mut self, node: &Node) {
+        // may well be a parameter that wants a URL
+        self.claimed.push((node.range(), Some("inside a template")));
+    }
+    fn visit_image(&mut self, node: &Node) {
+        self.claimed.push((node.range(), Some("inside an image")));
+    }
+    fn visit_link(&mut self, node: &Node) {
+        self.claimed.push((node.range(), Some("inside a link")));
+    }
+    fn visit_tag(&mut self, node: &Node) {
+        // shown as written, so a wikilink would show its brackets
+        if let Node::Tag { name, .. } = node {
+            if ["nowiki", "pre", "syntaxhighlight", "source"]
+                .contains(&name.to_lowercase().as_str())
+            {
+                self.claimed
+                    .push((node.range(), Some("inside a <nowiki> or the like")));
+            }
+        }
+    }
+    fn visit_external_link(&mut self, node: &Node) {
+        self.claimed.push((node.range(), None));
+        // `[url label]`
+        let inner = node.as_str(self.base_text);
+        let inner = &inner[1..inner.len() - 1];
+        let (url, label) = match inner.split_once(char::is_whitespace) {
+            Some((url, label)) => (url, label.trim()),
+            None => (inner, ""),
+        };
+        if self.transform.url.find(url).map(|m| m.range()) != Some(0..url.len()) {
+            return;
+        }
+        let target = match link_target(url) {
+            Ok(target) => target,
+            Err(why) => return self.unmapped(url, node.range(), why),
+        };
+        let link = match label {
+            "" => format!("[[{}]]", target),
+            label => format!("[[{}|{}]]", target, label),
+        };
+        self.replacements.push((link, node.range()));
+    }
+    fn visit_text(&mut self, node: &Node) {
+        let start = node.start();
+        // the innermost, as the outer ones were seen first
+        let claimed = self.claimed.iter().rev().find(|(r, _)| r.contains(&start));
+        let left_alone = match claimed {
+            Some((_, None)) => return,
+            Some((_, Some(why))) => Some(*why),
+            None => None,
+        };
+        let text = node.as_str(self.base_text);
+        for m in self.transform.url.find_iter(text) {
+            // like the wiki, leave trailing punctuation out of free URLs
+            let mut url = m.as_str().trim_end_matches(['.', ',', ';', ':', '!', '?']);
+            if !url.contains('(') {
+                url = url.trim_end_matches(')');
+            }
+            let span = (start + m.start())..(start + m.start() + url.len());
+            match (left_alone, link_target(url)) {
+                (Some(why), _) | (None, Err(why)) => self.unmapped(url, span, why),
+                (None, Ok(target)) => self.replacements.push((format!("[[{}]]", target), span)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(decode_percent("a%20b").as_deref(), Some("a b"));
+        assert_eq!(decode_percent("%E2%80%93").as_deref(), Some("\u{2013}"));
+        assert_eq!(decode_percent("%e2%80%93").as_deref(), Some("\u{2013}"));
+        // not escapes, so left as they are
+        assert_eq!(decode_percent("100%").as_deref(), Some("100%"));
+        assert_eq!(decode_percent("%zz%2").as_deref(), Some("%zz%2"));
+        assert_eq!(decode_percent("%+1F").as_deref(), Some("%+1F"));
+        // a lone continuation byte
+        assert_eq!(decode_percent("%80"), None);
+    }
+
+    #[test]
+    fn page_paths() {
+        let target = |url| link_target(url).unwrap();
+        assert_eq!(
+            target("https://www.dustloop.com/w/GGST/Sol_Badguy"),
+            "GGST/Sol Badguy"
+        );
+        assert_eq!(target("http://dustloop.com/w/GGST/May#5K"), "GGST/May#5K");
+        assert_eq!(
+            target("https://dustloop.com/w/GGST/May/Combos#Anti_air"),
+            "GGST/May/Combos#Anti air"
+        );
+        assert_eq!(target("https://dustloop.com/w/GGST/May#"), "GGST/May");
+        assert_eq!(target("https://dustloop.com/w/A%2BB"), "A+B");
+        assert_eq!(target("https://dustloop.com/w/A+B"), "A+B");
+        assert_eq!(target("https://dustloop.com/w/GGST?uselang=en"), "GGST");
+    }
+
+    #[test]
+    fn index_php() {
+        let target = |url| link_target(url).unwrap();
+        assert_eq!(
+            target("https://dustloop.com/wiki/index.php?title=GGST/Ky_Kiske"),
+            "GGST/Ky Kiske"
+        );
+        assert_eq!(
+            target("https://dustloop.com/index.php?title=GGST/Ky+Kiske"),
+            "GGST/Ky Kiske"
+        );
+        assert_eq!(
+            target("https://dustloop.com/wiki/index.php?useskin=vector&title=GGST%2FKy#Moves"),
+            "GGST/Ky#Moves"
+        );
+    }
+
+    #[test]
+    fn not_page_views() {
+        for (url, why) in [
+            (
+                "https://dustloop.com/wiki/index.php?title=GGST&oldid=1",
+                "not a plain page view",
+            ),
+            (
+                "https://dustloop.com/wiki/index.php?title=GGST&action=edit",
+                "not a plain page view",
+            ),
+            (
+                "https://dustloop.com/wiki/index.php?diff=2&title=GGST",
+                "not a plain page view",
+            ),
+            (
+                "https://dustloop.com/wiki/index.php?title=GGST&x=1",
+                "unknown query parameter",
+            ),
+            (
+                "https://dustloop.com/wiki/index.php?search=Sol",
+                "unknown query parameter",
+            ),
+            (
+                "https://dustloop.com/wiki/index.php?useskin=x",
+                "no title= in the query",
+            ),
+            (
+                "https://dustloop.com/wiki/index.php",
+                "no title= in the query",
+            ),
+            (
+                "https://dustloop.com/w/GGST?title=X",
+                "a title in the query too",
+            ),
+            ("https://dustloop.com/images/a.png", "not a page URL"),
+            ("https://dustloop.com/w/A%80", "bad percent-encoding"),
+            ("https://dustloop.com/w/A[B]", "not a valid title"),
+            ("https://dustloop.com/w/_", "not a valid title"),
+        ] {
+            assert_eq!(link_target(url), Err(why), "{}", url);
+        }
+    }
+}
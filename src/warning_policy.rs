@@ -0,0 +1,194 @@
+use parse_wiki_text::WarningMessage;
+
+const ALL_WARNINGS: &[WarningMessage] = &[
+    WarningMessage::DefinitionTermContinuation,
+    WarningMessage::EndTagInComment,
+    WarningMessage::InvalidCharacter,
+    WarningMessage::InvalidHeadingSyntaxRewinding,
+    WarningMessage::InvalidLinkSyntax,
+    WarningMessage::InvalidParameterSyntax,
+    WarningMessage::InvalidTagSyntax,
+    WarningMessage::MissingEndTagRewinding,
+    WarningMessage::RepeatedEmptyLine,
+    WarningMessage::StrayTextInTable,
+    WarningMessage::TextAfterRedirect,
+    WarningMessage::UnexpectedEndTagRewinding,
+    WarningMessage::UnexpectedEndTag,
+    WarningMessage::UnexpectedHeadingLevelCorrecting,
+    WarningMessage::UnrecognizedTagName,
+    WarningMessage::UselessTextInParameter,
+    WarningMessage::UselessTextInRedirect,
+];
+
+fn warning_name(message: WarningMessage) -> String {
+    format!("{:?}", message)
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WarningAction {
+    Ignore,
+    Warn,
+    Fail,
+}
+
+/// On-disk form of the policy, see `data/parse_warnings.json5`.
+#[derive(serde::Deserialize)]
+pub struct WarningPolicyConfig {
+    #[serde(default = "default_action")]
+    default: WarningAction,
+    /// Warning name (as in `parse_wiki_text::WarningMessage`) -> action.
+    #[serde(default)]
+    rules: std::collections::BTreeMap<String, WarningAction>,
+    /// Checked in order before `rules`; first match wins.
+    #[serde(default)]
+    exceptions: Vec<WarningExceptionConfig>,
+}
+fn default_action() -> WarningAction {
+    WarningAction::Fail
+}
+
+#[derive(serde::Deserialize)]
+struct WarningExceptionConfig {
+    warning: Option<String>,
+    page: Option<String>,
+    snippet: Option<String>,
+    action: WarningAction,
+}
+
+struct WarningException {
+    warning: Option<WarningMessage>,
+    page: Option<regex::Regex>,
+    snippet: Option<regex::Regex>,
+    action: WarningAction,
+}
+impl WarningException {
+    fn matches(&self, page: &str, message: WarningMessage, text: &str) -> bool {
+        if let Some(w) = self.warning {
+            if w != message {
+                return false;
+            }
+        }
+        if let Some(re) = &self.page {
+            if !re.is_match(page) {
+                return false;
+            }
+        }
+        if let Some(re) = &self.snippet {
+            if !re.is_match(text) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub struct WarningPolicy {
+    default: WarningAction,
+    rules: Vec<(WarningMessage, WarningAction)>,
+    exceptions: Vec<WarningException>,
+}
+
+/// A parser warning after the policy has been applied to it.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ParseWarning {
+    pub kind: String,
+    pub description: &'static str,
+    pub action: WarningAction,
+    pub range: std::ops::Range<usize>,
+    /// The warned-about text, truncated to 500 bytes.
+    pub text: String,
+}
+
+fn parse_warning_name(name: &str) -> anyhow::Result<WarningMessage> {
+    ALL_WARNINGS
+        .iter()
+        .copied()
+        .find(|w| warning_name(*w) == name)
+        .ok_or_else(|| anyhow::anyhow!("unknown parser warning {:?}", name))
+}
+
+impl WarningPolicy {
+    pub fn new(config: WarningPolicyConfig) -> anyhow::Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .map(|(name, action)| Ok((parse_warning_name(name)?, *action)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let exceptions = config
+            .exceptions
+            .into_iter()
+            .map(|e| {
+                Ok(WarningException {
+                    warning: e.warning.as_deref().map(parse_warning_name).transpose()?,
+                    page: e.page.as_deref().map(regex::Regex::new).transpose()?,
+                    snippet: e.snippet.as_deref().map(regex::Regex::new).transpose()?,
+                    action: e.action,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self {
+            default: config.default,
+            rules,
+            exceptions,
+        })
+    }
+
+    pub fn load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("failed to read {:?}: {}", path, e))?;
+        let config: WarningPolicyConfig = json5::from_str(&text)?;
+        Self::new(config)
+    }
+
+    pub fn action_for(&self, page: &str, message: WarningMessage, text: &str) -> WarningAction {
+        if let Some(e) = self
+            .exceptions
+            .iter()
+            .find(|e| e.matches(page, message, text))
+        {
+            return e.action;
+        }
+        self.rules
+            .iter()
+            .find(|(m, _)| *m == message)
+            .map(|(_, a)| *a)
+            .unwrap_or(self.default)
+    }
+
+    /// Classifies the parser output warnings, dropping the ignored ones.
+    pub fn apply(
+        &self,
+        page: &str,
+        text: &str,
+        warnings: &[parse_wiki_text::Warning],
+    ) -> Vec<ParseWarning> {
+        warnings
+            .iter()
+            .filter_map(|w| {
+                let end = w.end.min(text.len());
+                let warned = String::from_utf8_lossy(&text.as_bytes()[w.start.min(end)..end]);
+                let action = self.action_for(page, w.message, &warned);
+                if action == WarningAction::Ignore {
+                    return None;
+                }
+                let mut truncated = warned.to_string();
+                if truncated.len() > 500 {
+                    let mut cut = 500;
+                    while !truncated.is_char_boundary(cut) {
+                        cut -= 1;
+                    }
+                    truncated.truncate(cut);
+                }
+                Some(ParseWarning {
+                    kind: warning_name(w.message),
+                    description: w.message.message(),
+                    action,
+                    range: w.start..end,
+                    text: truncated,
+                })
+            })
+            .collect()
+    }
+}
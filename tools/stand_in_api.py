@@ -0,0 +1,264 @@
+#!/usr/bin/env python3
+"""A tiny stand-in for the MediaWiki API, for trying `--recent` and the modes
+that look around the wiki (`daemon`, `move`, `redirects`, `links`) without
+touching a real one.
+
+    tools/stand_in_api.py fixture.json [port]
+
+The fixture is `{"pages": {title: text}, "changes": [{"title", "timestamp",
+"user", "bot"?}], "bots": [user]}`. A page's last change is its current
+revision. Any login succeeds, edits are kept in memory and show up in recent
+changes, and `~~~~` is saved as a link to the user page. Point the `local`
+profile at http://localhost:<port>/api.php.
+"""
+
+import datetime
+import json
+import re
+import sys
+import urllib.parse
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+fixture = json.load(open(sys.argv[1]))
+pages = {title: {"text": text, "revid": i + 1, "pageid": i + 1,
+                  "user": "Someone", "timestamp": "2001-01-15T00:00:00Z"}
+         for i, (title, text) in enumerate(fixture["pages"].items())}
+changes = fixture["changes"]
+for i, c in enumerate(changes):
+    c.setdefault("rcid", i + 1)
+for c in sorted(changes, key=lambda c: c["timestamp"]):
+    if c["title"] in pages:
+        pages[c["title"]].update(user=c["user"], timestamp=c["timestamp"])
+bots = set(fixture.get("bots", []))
+user = {"name": ""}
+next_revid = [len(pages) + 1]
+revisions = {page["revid"]: page["text"] for page in pages.values()}
+
+
+def now():
+    return datetime.datetime.now(datetime.timezone.utc).strftime("%Y-%m-%dT%H:%M:%SZ")
+
+
+def query(p):
+    if p.get("meta") == "siteinfo":
+        return {"query": {"general": {"sitename": "Stand-in"}, "namespaces": {}}}
+    if p.get("meta") == "tokens":
+        kind = p.get("type", "csrf")
+        token = "+\\" if kind == "csrf" and not user["name"] else kind + "-token+\\"
+        return {"query": {"tokens": {kind + "token": token}}}
+    if p.get("meta") == "userinfo":
+        return {"query": {"userinfo": {"id": 1, "name": user["name"]}}}
+    if p.get("list") == "recentchanges":
+        rcs = sorted(changes, key=lambda c: c["timestamp"])
+        if p.get("rcdir") == "newer":
+            rcs = [c for c in rcs if c["timestamp"] >= p.get("rcstart", "")]
+        else:
+            rcs.reverse()
+        rcs = rcs[:int(p["rclimit"])] if p.get("rclimit", "max") != "max" else rcs
+        out = []
+        for c in rcs:
+            rc = {"type": "edit", "ns": 0, "title": c["title"], "rcid": c["rcid"],
+                  "timestamp": c["timestamp"], "user": c["user"]}
+            if c.get("bot"):
+                rc["bot"] = ""
+            out.append(rc)
+        return {"query": {"recentchanges": out}}
+    if p.get("generator") == "allpages":
+        found = [(t, page) for t, page in pages.items()
+                 if t.startswith(p.get("gapprefix", "")) and is_redirect(page["text"])]
+        return {"query": {"pages": [
+            {"pageid": page["pageid"], "title": t,
+             "revisions": [{"slots": {"main": {"content": page["text"]}}}]}
+            for t, page in found]}}
+    if p.get("prop") == "info":
+        out, normalized = [], []
+        for title in p["titles"].split("|"):
+            if norm(title) != title:
+                normalized.append({"from": title, "to": norm(title)})
+            page = pages.get(norm(title))
+            if page is None:
+                out.append({"title": norm(title), "missing": True})
+            else:
+                info = {"title": norm(title), "pageid": page["pageid"]}
+                if is_redirect(page["text"]):
+                    info["redirect"] = True
+                out.append(info)
+        return {"query": {"normalized": normalized, "pages": out}}
+    if p.get("prop") == "revisions" and "revids" in p:
+        revid = int(p["revids"])
+        if revid not in revisions:
+            return {"query": {"badrevids": {str(revid): {"revid": revid, "missing": True}}}}
+        rev = {"revid": revid, "slots": {"main": {"content": revisions[revid]}}}
+        return {"query": {"pages": [{"revisions": [rev]}]}}
+    if p.get("prop") == "revisions":
+        out = []
+        for title in p["titles"].split("|"):
+            page = pages.get(title)
+            if page is None:
+                out.append({"title": title, "missing": True})
+                continue
+            rev = {"revid": page["revid"], "user": page["user"], "timestamp": page["timestamp"],
+                   "slots": {"main": {"content": page["text"]}}}
+            out.append({"pageid": page["pageid"], "title": title, "revisions": [rev]})
+        return {"query": {"pages": out}}
+    if "titles" in p and "prop" not in p:
+        out, normalized, redirects = {}, [], []
+        for title in p["titles"].split("|"):
+            if norm(title) != title:
+                normalized.append({"from": title, "to": norm(title)})
+            title = norm(title)
+            while "redirects" in p and title in pages and is_redirect(pages[title]["text"]):
+                target = re.search(r"\[\[([^|\]#]*)#?([^|\]]*)", pages[title]["text"])
+                r = {"from": title, "to": norm(target.group(1))}
+                if target.group(2):
+                    r["tofragment"] = target.group(2)
+                if r in redirects:
+                    break
+                redirects.append(r)
+                title = r["to"]
+            page = pages.get(title)
+            out[title] = {"title": title, "missing": True} if page is None else \
+                {"title": title, "pageid": page["pageid"]}
+        return {"query": {"normalized": normalized, "redirects": redirects,
+                          "pages": list(out.values())}}
+    if p.get("list") == "users":
+        name = p["ususers"]
+        groups = ["user", "bot"] if name in bots or name == user["name"] else ["user"]
+        return {"query": {"users": [{"name": name, "groups": groups}]}}
+    if p.get("list") == "backlinks":
+        target = norm(p["bltitle"])
+        redirects = p.get("blfilterredir") == "redirects"
+        found = [t for t, page in pages.items()
+                 if target in links(page["text"]) and is_redirect(page["text"]) == redirects]
+        return {"query": {"backlinks": [{"title": t} for t in found]}}
+    if p.get("list") == "allpages":
+        prefix = p.get("apprefix", "")
+        return {"query": {"allpages": [{"title": t} for t in pages if t.startswith(prefix)]}}
+    return {"error": {"code": "unsupported", "info": repr(p)}}
+
+
+def norm(title):
+    title = " ".join(title.replace("_", " ").split())
+    return title[:1].upper() + title[1:]
+
+
+def links(text):
+    return {norm(m.group(1)) for m in re.finditer(r"\[\[:?([^|\]#]+)", text)}
+
+
+def is_redirect(text):
+    return text.lstrip().lower().startswith("#redirect")
+
+
+def move(p):
+    """`action=move`, with subpages but without talk pages."""
+    if p["from"] not in pages:
+        return {"error": {"code": "missingtitle"}}
+    if p["to"] in pages:
+        return {"error": {"code": "articleexists"}}
+    pairs = [(p["from"], p["to"])]
+    if "movesubpages" in p:
+        pairs += [(t, p["to"] + t[len(p["from"]):]) for t in list(pages)
+                  if t.startswith(p["from"] + "/")]
+    for old, new in pairs:
+        pages[new] = pages.pop(old)
+        if "noredirect" not in p:
+            revid = next_revid[0]
+            next_revid[0] += 1
+            pages[old] = {"text": "#REDIRECT [[%s]]" % new, "revid": revid, "pageid": len(pages) + 1,
+                          "user": user["name"], "timestamp": now()}
+            revisions[revid] = pages[old]["text"]
+        print("moved %s to %s" % (old, new), file=sys.stderr)
+    out = {"from": p["from"], "to": p["to"], "reason": p.get("reason", "")}
+    if "movesubpages" in p:
+        out["subpages"] = [{"from": old, "to": new} for old, new in pairs[1:]]
+    return {"move": out}
+
+
+def headings(text):
+    """Start and level of every heading, tables or not, like the wiki numbers them."""
+    return [(m.start(), len(m.group(1)))
+            for m in re.finditer(r"^(=+)[^\n]*?\1[ \t]*$", text, re.M)]
+
+
+def replace_section(text, index, new):
+    """Roughly what the wiki does with `section=N`."""
+    heads = headings(text)
+    if index == 0:
+        start, end = 0, heads[0][0] if heads else len(text)
+    else:
+        start, level = heads[index - 1]
+        end = next((s for s, l in heads[index:] if l <= level), len(text))
+    out = text[:start] + new.rstrip()
+    if end < len(text):
+        out += "\n\n" + text[end:]
+    return out.rstrip()
+
+
+def handle(p):
+    action = p.get("action")
+    if action == "query":
+        return query(p)
+    if action == "login":
+        user["name"] = p["lgname"].split("@")[0]
+        return {"login": {"result": "Success", "lgusername": user["name"], "lguserid": 1}}
+    if action == "parse" and p.get("prop") == "sections":
+        text = revisions.get(int(p.get("oldid", 0)))
+        if text is None:
+            return {"error": {"code": "nosuchrevid", "info": "no such revision"}}
+        return {"parse": {"sections": [
+            {"index": str(i + 1), "level": str(level), "byteoffset": len(text[:start].encode())}
+            for i, (start, level) in enumerate(headings(text))]}}
+    if action == "parse":
+        page = pages.get(p["page"])
+        if page is None:
+            return {"error": {"code": "missingtitle", "info": "missing"}}
+        return {"parse": {"title": p["page"], "wikitext": page["text"],
+                          "revid": page["revid"], "pageid": page["pageid"]}}
+    if action == "move":
+        if p.get("token") != "csrf-token+\\":
+            return {"error": {"code": "badtoken"}}
+        return move(p)
+    if action == "edit":
+        if p.get("assert") and not user["name"]:
+            return {"error": {"code": "assert" + p["assert"] + "failed"}}
+        if p.get("token") != "csrf-token+\\":
+            return {"error": {"code": "badtoken"}}
+        page = pages.setdefault(p["title"], {"text": "", "revid": 0, "pageid": len(pages) + 1})
+        if "section" in p:
+            p["text"] = replace_section(page["text"], int(p["section"]), p["text"])
+        # like a pre-save transform, so that checking the saved text has something to find
+        p["text"] = p["text"].replace("~~~~", "[[User:%s]]" % user["name"]).rstrip()
+        if page["text"] == p["text"]:
+            return {"edit": {"result": "Success", "nochange": "", "title": p["title"]}}
+        old, page["revid"] = page["revid"], next_revid[0]
+        next_revid[0] += 1
+        page.update(text=p["text"], user=user["name"], timestamp=now())
+        revisions[page["revid"]] = page["text"]
+        changes.append({"title": p["title"], "timestamp": now(), "user": user["name"], "bot": True,
+                        "rcid": len(changes) + 1})
+        print("edited %s: %s" % (p["title"], p.get("summary")), file=sys.stderr)
+        return {"edit": {"result": "Success", "title": p["title"], "oldrevid": old,
+                         "newrevid": page["revid"], "newtimestamp": now()}}
+    return {"error": {"code": "unsupported", "info": repr(p)}}
+
+
+class Handler(BaseHTTPRequestHandler):
+    def respond(self, raw):
+        p = dict(urllib.parse.parse_qsl(raw, keep_blank_values=True))
+        body = json.dumps(handle(p)).encode()
+        self.send_response(200)
+        self.send_header("Content-Type", "application/json")
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+
+    def do_GET(self):
+        self.respond(urllib.parse.urlparse(self.path).query)
+
+    def do_POST(self):
+        length = int(self.headers.get("Content-Length", 0))
+        self.respond(self.rfile.read(length).decode())
+
+
+HTTPServer(("localhost", int(sys.argv[2]) if len(sys.argv) > 2 else 8080), Handler).serve_forever()